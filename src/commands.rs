@@ -1,176 +1,108 @@
 use std::fs::File;
-use std::process::{Command, Stdio};
-use std::io::{self, Write};
-use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
-use std::time::Instant;
-use crate::{parsing, Program, Logger};
-use crate::ProcessInfo;
+use std::process::{Child, Command, Stdio};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::Arc;
+use std::thread;
+use crate::config::Program;
+use crate::logger::Logger;
+use crate::syslog::{Severity, Syslog};
 use std::os::unix::process::CommandExt;
 use libc::{umask};
 
-pub fn start_program(program: &Program) -> Result<ProcessInfo, std::io::Error> {
+/// A stream piped to syslog, resolved before the spawn so a missing socket
+/// or file fails the attempt instead of orphaning the child.
+struct Forward {
+    file: Option<File>,
+    syslog: Arc<Syslog>,
+}
+
+fn output(path: &Option<String>, to_syslog: bool, logger: &Logger) -> io::Result<(Stdio, Option<Forward>)> {
+    if to_syslog {
+        let file = match path {
+            Some(path) => Some(File::create(path)?),
+            None => None,
+        };
+        return Ok((Stdio::piped(), Some(Forward { file, syslog: logger.output_syslog()? })));
+    }
+    match path {
+        Some(path) => Ok((File::create(path)?.into(), None)),
+        None => Ok((Stdio::null(), None)),
+    }
+}
+
+/// Copies each line the child writes to syslog, and to its output file when
+/// one is configured, until the pipe is closed. Lines that are not UTF-8
+/// are sent with the invalid bytes replaced; the pipe is drained to the end
+/// whatever happens, so the child never dies of SIGPIPE.
+fn forward_output<R: Read + Send + 'static>(reader: R, forward: Forward, severity: Severity, tag: String, pid: u32) -> io::Result<()> {
+    let Forward { mut file, syslog } = forward;
+    thread::Builder::new().spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+            if let Some(ref mut file) = file {
+                let _ = file.write_all(&line);
+            }
+            let message = String::from_utf8_lossy(&line);
+            let _ = syslog.send(severity, &tag, pid, "", message.trim_end_matches(['\n', '\r']));
+        }
+    })?;
+    Ok(())
+}
+
+pub fn spawn_process(name: &str, instance: u32, program: &Program, logger: &Logger) -> io::Result<Child> {
     let mut command_parts = program.cmd.split_whitespace();
-    let executable = command_parts.next().expect("Executable not found");
+    let executable = command_parts
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Executable not found"))?;
     let args: Vec<&str> = command_parts.collect();
+    let (stdout, stdout_forward) = output(&program.stdout, program.stdout_syslog, logger)?;
+    let (stderr, stderr_forward) = output(&program.stderr, program.stderr_syslog, logger)?;
     let mut command = Command::new(executable);
     command
         .args(args)
         .stdin(Stdio::null())
-        .stdout(File::create(&program.stdout)?)
-        .stderr(File::create(&program.stderr)?)
+        .stdout(stdout)
+        .stderr(stderr)
 		.current_dir(&program.workingdir);
     if let Some(ref env) = program.env {
         command.envs(env);
     };
-    let new_umask = u16::from_str_radix(&program.umask, 8).expect("Failed to parse umask");
+    let new_umask = program.umask as libc::mode_t;
     unsafe {
         command.pre_exec(move || {
-            umask(new_umask.into());
+            umask(new_umask);
+            // Lead a process group so stops also reach the grandchildren.
+            libc::setpgid(0, 0);
             Ok(())
         });
     }
-    let child = command.spawn()?;
-    Ok(ProcessInfo {
-        child,
-        start_time: Instant::now(),
-        time_elapsed_since_stop: None,
-		successfully_started: false,
-		stopped_by_signal: false
-    })
-}
-
-pub fn autostart_programs(programs: &Arc<Mutex<HashMap<String, Program>>>, processes: &Arc<Mutex<HashMap<String, Vec<ProcessInfo>>>>, logger: &Arc<Logger>) {
-    let programs = programs.lock().unwrap();
-    let mut processes = processes.lock().unwrap();
-
-    for (name, program) in programs.iter() {
-        if program.autostart {
-            let mut instances = Vec::new();
-
-            for i in 0..program.numprocs {
-                let mut attempts = 0;
-                while attempts < program.startretries {
-                    match start_program(program) {
-                        Ok(process_info) => {
-                            instances.push(process_info);
-                            if let Some(last_process_info) = instances.last_mut() {
-                                check_running_time(name, last_process_info, program.starttime.into(), &logger);
-                            }
-                            logger.log_formatted("Started", format_args!("{} instance {}", name, i))
-                                .expect("Failed to log message");
-                            println!("Started {} instance {}", name, i);
-                            break;
-                        }
-                        Err(e) => {
-                            eprintln!("Failed to start {} instance {}: {}", name, i, e);
-                            attempts += 1;
-                            if attempts < program.startretries {
-                                logger.log_formatted("Retry", format_args!("Retrying to start {} instance {} (attempt {}/{})", name, i, attempts + 1, program.startretries))
-                                    .expect("Failed to log message");
-                                eprintln!("Retrying to start {} instance {} (attempt {}/{})", name, i, attempts + 1, program.startretries);
-                            }
-                        }
-                    }
-                }
-            }
-
-            if !instances.is_empty() {
-                processes.insert(name.clone(), instances);
-            }
-        }
+    let mut child = command.spawn()?;
+    let tag = format!("{}.{}", name, instance);
+    let forwarded = forward_streams(&mut child, stdout_forward, stderr_forward, tag);
+    if let Err(e) = forwarded {
+        // Nothing will supervise a child whose spawn is reported as failed.
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(e);
     }
+    Ok(child)
 }
 
-pub fn reload_config(programs: &Arc<Mutex<HashMap<String, Program>>>, processes: &Arc<Mutex<HashMap<String, Vec<ProcessInfo>>>>, logger: &Arc<Logger>) {
-    let new_programs = parsing();
-    let mut programs = programs.lock().unwrap();
-    let mut processes = processes.lock().unwrap();
-
-    for (name, program) in &new_programs {
-        if let Some(existing_program) = programs.get(name) {
-            if existing_program != program {
-                if let Some(children) = processes.remove(name) {
-					for (i, mut process_info) in children.into_iter().enumerate() {
-                        if process_info.child.kill().is_ok() {
-                            logger.log_formatted("Killed", format_args!("{} instance {}", name, i))
-                                .expect("Failed to log message");
-                            println!("Killed {} instance {}", name, i);
-                        }
-					}
-                }
-            }
-        }
-    }
-
-    let old_programs: Vec<String> = programs.keys().cloned().collect();
-    for old_program in old_programs {
-        if !new_programs.contains_key(&old_program) {
-            if let Some(children) = processes.remove(&old_program) {
-				for (i, mut process_info) in children.into_iter().enumerate() {
-                    if process_info.child.kill().is_ok() {
-                        logger.log_formatted("Killed", format_args!("{} instance {}", old_program, i))
-                            .expect("Failed to log message");
-                        println!("Killed {} instance {}", old_program, i);
-                    }
-                }
-            }
-        }
-    }
-
-    *programs = new_programs;
-
-	for (name, program) in programs.iter() {
-        if program.autostart && (!processes.contains_key(name) || processes.get(name).unwrap().is_empty()) {
-            let mut instances = Vec::new();
-            for i in 0..program.numprocs {
-                let mut attempts = 0;
-                while attempts < program.startretries {
-                    match start_program(program) {
-                        Ok(process_info) => {
-                            instances.push(process_info);
-                            if let Some(last_process_info) = instances.last_mut() {
-                                check_running_time(name, last_process_info, program.starttime.into(), &logger);
-                            }
-                            logger.log_formatted("Started", format_args!("{} instance {}", name, i))
-                                .expect("Failed to log message");
-                            println!("Started {} instance {}", name, i);
-                            break;
-                        }
-                        Err(e) => {
-                            eprintln!("Failed to start {} instance {}: {}", name, i, e);
-                            attempts += 1;
-                            if attempts < program.startretries {
-                                logger.log_formatted("Retry", format_args!("Retrying to start {} instance {} (attempt {}/{})", name, i, attempts + 1, program.startretries))
-                                    .expect("Failed to log message");
-                                eprintln!("Retrying to start {} instance {} (attempt {}/{})", name, i, attempts + 1, program.startretries);
-                            }
-                        }
-                    }
-                }
-
-                if attempts >= program.startretries {
-                    logger.log_formatted("Failed", format_args!("Failed to start {} instance {} after {} attempts", name, i, program.startretries))
-                        .expect("Failed to log message");
-                    eprintln!("Failed to start {} instance {} after {} attempts", name, i, program.startretries);
-                }
-            }
-
-            if !instances.is_empty() {
-                processes.insert(name.clone(), instances);
-            }
-        }
+fn forward_streams(child: &mut Child, stdout_forward: Option<Forward>, stderr_forward: Option<Forward>, tag: String) -> io::Result<()> {
+    let pid = child.id();
+    if let (Some(stdout), Some(forward)) = (child.stdout.take(), stdout_forward) {
+        forward_output(stdout, forward, Severity::Info, tag.clone(), pid)?;
     }
-}
-
-pub fn check_running_time(program_name: &str, process_info: &mut ProcessInfo, starttime: u64, logger: &Arc<Logger>) {
-    let elapsed_time = process_info.start_time.elapsed().as_secs();
-    if !process_info.successfully_started && elapsed_time >= starttime {
-        let message = format!("{} successfully started ({} seconds)", program_name, elapsed_time);
-        println!("{}", message);
-        logger.log(&message).expect("Failed to log message");
-		print!("> ");
-		io::stdout().flush().expect("Flush error");
-        process_info.successfully_started = true;
+    if let (Some(stderr), Some(forward)) = (child.stderr.take(), stderr_forward) {
+        forward_output(stderr, forward, Severity::Err, tag, pid)?;
     }
+    Ok(())
 }
@@ -0,0 +1,313 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::{Deserialize, Deserializer};
+use serde::de::{self, MapAccess, Visitor};
+
+/// The longest backoff, in seconds, jitter included: a day.
+const MAX_BACKOFF: f64 = 86_400.0;
+use crate::logger::LoggingConfig;
+
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoRestart {
+    Always,
+    Unexpected,
+    Never,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+pub struct Program {
+    pub cmd: String,
+    pub numprocs: u32,
+    #[serde(deserialize_with = "octal")]
+    pub umask: u32,
+    pub workingdir: String,
+    pub autostart: bool,
+    pub autorestart: AutoRestart,
+    pub exitcodes: Vec<i32>,
+    pub startretries: u32,
+    pub starttime: u32,
+    pub stopsignal: i32,
+    pub stoptime: u32,
+    /// Lower priorities start first and stop last.
+    #[serde(default = "default_priority")]
+    pub priority: i32,
+    /// Programs that must be RUNNING before this one starts.
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Seconds to wait before the first retry or autorestart after a failed
+    /// start. Each failure in a row multiplies it by `backoff_multiplier`,
+    /// up to `backoff_max`, until the instance stays up past `starttime`.
+    #[serde(default = "default_backoff_initial")]
+    pub backoff_initial: f64,
+    #[serde(default = "default_backoff_max")]
+    pub backoff_max: f64,
+    #[serde(default = "default_backoff_multiplier")]
+    pub backoff_multiplier: f64,
+    /// How far each delay may be moved at random, as a fraction of it.
+    #[serde(default = "default_backoff_jitter")]
+    pub backoff_jitter: f64,
+    /// Autorestarts allowed within `restart_window` seconds, after which
+    /// the instance is given up on as FATAL. Unlimited when unset.
+    #[serde(default)]
+    pub max_restarts: Option<u32>,
+    #[serde(default = "default_restart_window")]
+    pub restart_window: u32,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    #[serde(default)]
+    pub stdout_syslog: bool,
+    #[serde(default)]
+    pub stderr_syslog: bool,
+    pub env: Option<HashMap<String, String>>,
+}
+
+impl AutoRestart {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AutoRestart::Always => "always",
+            AutoRestart::Unexpected => "unexpected",
+            AutoRestart::Never => "never",
+        }
+    }
+}
+
+impl Program {
+    /// Whether an exit with `exit_code` should trigger an automatic restart.
+    pub fn should_restart(&self, exit_code: i32) -> bool {
+        match self.autorestart {
+            AutoRestart::Always => true,
+            AutoRestart::Unexpected => !self.exitcodes.contains(&exit_code),
+            AutoRestart::Never => false,
+        }
+    }
+
+    /// The delay before the next attempt after `failures` failed starts in
+    /// a row, jitter included.
+    pub fn backoff(&self, failures: u32) -> Duration {
+        if self.backoff_initial <= 0.0 {
+            return Duration::ZERO;
+        }
+        let exponent = failures.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = (self.backoff_initial * self.backoff_multiplier.powi(exponent)).min(self.backoff_max);
+        // A fresh RandomState is randomly seeded, which is all the jitter needs.
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        let jitter = delay * self.backoff_jitter * (2.0 * random - 1.0);
+        Duration::try_from_secs_f64((delay + jitter).clamp(0.0, MAX_BACKOFF)).unwrap_or_default()
+    }
+}
+
+fn default_priority() -> i32 {
+    999
+}
+
+fn default_backoff_initial() -> f64 {
+    1.0
+}
+
+fn default_backoff_max() -> f64 {
+    60.0
+}
+
+fn default_backoff_multiplier() -> f64 {
+    2.0
+}
+
+fn default_backoff_jitter() -> f64 {
+    0.1
+}
+
+fn default_restart_window() -> u32 {
+    60
+}
+
+fn octal<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let umask = String::deserialize(deserializer)?;
+    u32::from_str_radix(&umask, 8).map_err(|_| de::Error::custom(format!("invalid umask {:?}", umask)))
+}
+
+/// Which events the interactive shell shows as they happen.
+#[derive(Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Notifications {
+    /// Only failures: unexpected exits, kills, FATAL and failed reloads.
+    Quiet,
+    /// Failures and the instances becoming RUNNING, exiting, backing off or stopped.
+    #[default]
+    Normal,
+    /// Every event.
+    Verbose,
+}
+
+impl Notifications {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Notifications::Quiet => "quiet",
+            Notifications::Normal => "normal",
+            Notifications::Verbose => "verbose",
+        }
+    }
+
+    pub fn parse(level: &str) -> Option<Self> {
+        match level {
+            "quiet" => Some(Notifications::Quiet),
+            "normal" => Some(Notifications::Normal),
+            "verbose" => Some(Notifications::Verbose),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+pub struct ShellConfig {
+    #[serde(default)]
+    pub notifications: Notifications,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Config {
+    pub logging: LoggingConfig,
+    pub shell: ShellConfig,
+    pub programs: HashMap<String, Program>,
+    /// Named sets of programs, addressable as `group:*` or `group:member`.
+    pub groups: HashMap<String, Vec<String>>,
+    /// The file this config was read from, re-read on reload.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(serde_yaml::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "Failed to read {}: {}", path.display(), e),
+            ConfigError::Parse(e) => write!(f, "Failed to parse config: {}", e),
+            ConfigError::Invalid(message) => write!(f, "Invalid config: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        let mut config = Config::parse(&content)?;
+        config.path = Some(path.to_path_buf());
+        Ok(config)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, ConfigError> {
+        let config: Config = serde_yaml::from_str(content).map_err(ConfigError::Parse)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        for (group, members) in &self.groups {
+            if self.programs.contains_key(group) {
+                return Err(ConfigError::Invalid(format!("group {} has the name of a program", group)));
+            }
+            if let Some(member) = members.iter().find(|member| !self.programs.contains_key(*member)) {
+                return Err(ConfigError::Invalid(format!("group {} lists unknown program {}", group, member)));
+            }
+        }
+        let mut names: Vec<&String> = self.programs.keys().collect();
+        names.sort();
+        for name in &names {
+            let program = &self.programs[*name];
+            // Written so that NaN fails too.
+            let delays = program.backoff_initial >= 0.0 && program.backoff_max >= program.backoff_initial && program.backoff_max <= MAX_BACKOFF;
+            if !delays {
+                return Err(ConfigError::Invalid(format!("program {} needs 0 <= backoff_initial <= backoff_max <= {}", name, MAX_BACKOFF)));
+            }
+            let growth = program.backoff_multiplier >= 1.0 && (0.0..=1.0).contains(&program.backoff_jitter);
+            if !growth {
+                return Err(ConfigError::Invalid(format!("program {} needs backoff_multiplier >= 1 and backoff_jitter between 0 and 1", name)));
+            }
+            if program.restart_window == 0 {
+                return Err(ConfigError::Invalid(format!("program {} needs a restart_window of at least 1 second", name)));
+            }
+            if let Some(dependency) = self.programs[*name].depends_on.iter().find(|dependency| !self.programs.contains_key(*dependency)) {
+                return Err(ConfigError::Invalid(format!("program {} depends on unknown program {}", name, dependency)));
+            }
+        }
+        let mut done = Vec::new();
+        for name in names {
+            self.check_cycles(name, &mut Vec::new(), &mut done)?;
+        }
+        Ok(())
+    }
+
+    /// Walks the dependencies of `name` depth first, failing on one that is
+    /// already on `path`.
+    fn check_cycles<'a>(&'a self, name: &'a String, path: &mut Vec<&'a String>, done: &mut Vec<&'a String>) -> Result<(), ConfigError> {
+        if done.contains(&name) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|step| *step == name) {
+            let cycle: Vec<&str> = path[start..].iter().chain([&name]).map(|step| step.as_str()).collect();
+            return Err(ConfigError::Invalid(format!("dependency cycle: {}", cycle.join(" -> "))));
+        }
+        path.push(name);
+        for dependency in &self.programs[name].depends_on {
+            self.check_cycles(dependency, path, done)?;
+        }
+        path.pop();
+        done.push(name);
+        Ok(())
+    }
+}
+
+// Programs sit at the top level of config.yml next to reserved sections, so
+// the map is walked by hand instead of going through `#[serde(flatten)]`,
+// which would lose serde_yaml's coercion of scalars such as `ANSWER: 42`.
+impl<'de> Deserialize<'de> for Config {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ConfigVisitor;
+
+        impl<'de> Visitor<'de> for ConfigVisitor {
+            type Value = Config;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map of programs")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Config, A::Error> {
+                let mut config = Config {
+                    logging: LoggingConfig::default(),
+                    shell: ShellConfig::default(),
+                    programs: HashMap::new(),
+                    groups: HashMap::new(),
+                    path: None,
+                };
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "logging" => config.logging = map.next_value()?,
+                        "groups" => config.groups = map.next_value()?,
+                        "shell" => config.shell = map.next_value()?,
+                        _ => {
+                            let program = map.next_value()?;
+                            config.programs.insert(key, program);
+                        }
+                    }
+                }
+                Ok(config)
+            }
+        }
+
+        deserializer.deserialize_map(ConfigVisitor)
+    }
+}
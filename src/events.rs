@@ -0,0 +1,45 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+
+/// A state change of a supervised process, as published to subscribers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Spawned { program: String, instance: u32, pid: u32 },
+    SpawnFailed { program: String, instance: u32, error: String },
+    Running { program: String, instance: u32, pid: u32 },
+    Exited { program: String, instance: u32, pid: u32, status: i32, expected: bool },
+    /// The instance is tried again in `delay_ms`.
+    Backoff { program: String, instance: u32, delay_ms: u64 },
+    Stopping { program: String, instance: u32, pid: u32 },
+    Stopped { program: String, instance: u32, pid: u32 },
+    Killed { program: String, instance: u32, pid: u32 },
+    Paused { program: String, instance: u32, pid: u32 },
+    Resumed { program: String, instance: u32, pid: u32 },
+    Fatal { program: String, instance: u32 },
+    /// An instance of `dependency`, which `program` depends on, went FATAL.
+    DependencyFatal { program: String, dependency: String },
+    Reloaded,
+    ReloadFailed { error: String },
+}
+
+/// Fans events out to every live subscriber, dropping the ones whose
+/// receiver has gone away.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<Sender<Event>>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn emit(&self, event: Event) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
@@ -0,0 +1,197 @@
+use std::borrow::Cow;
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use std::sync::Arc;
+use rustyline::{Context, Helper};
+use taskmaster::{signals, Notifications, Supervisor};
+use crate::notifier::Notifier;
+use crate::shell::{split_instance, COMMANDS};
+
+const FLAGS: &[&str] = &["--json", "--groups", "--wait", "--no-wait"];
+
+/// Completes commands, targets and signal names, hints the rest of a
+/// unique completion and shows unknown commands and targets in red. It
+/// also tells `notifier` whether a line is being typed.
+pub struct ShellHelper {
+    supervisor: Supervisor,
+    notifier: Arc<Notifier>,
+}
+
+/// What an argument of a command is expected to be.
+enum Argument {
+    Command,
+    Target,
+    Program,
+    Signal,
+    Reset,
+    Level,
+    Nothing,
+}
+
+impl ShellHelper {
+    pub fn new(supervisor: Supervisor, notifier: Arc<Notifier>) -> Self {
+        ShellHelper { supervisor, notifier }
+    }
+
+    /// The argument `index` of `command`, flags not counted.
+    fn argument(command: &str, index: usize) -> Argument {
+        match (command, index) {
+            ("help", 1) => Argument::Command,
+            ("status", 1) => Argument::Target,
+            ("start" | "stop" | "restart" | "pause" | "resume" | "reset", _) => Argument::Target,
+            ("signal", 1) => Argument::Signal,
+            ("signal", _) => Argument::Target,
+            ("scale", 1) => Argument::Program,
+            ("scale", 2) => Argument::Reset,
+            ("notify", 1) => Argument::Level,
+            _ => Argument::Nothing,
+        }
+    }
+
+    fn targets(&self, word: &str) -> Vec<String> {
+        let groups = self.supervisor.groups();
+        if let Some((prefix, _)) = word.split_once(':') {
+            if let Some((group, members)) = groups.iter().find(|(group, _)| group == prefix) {
+                let mut targets = vec![format!("{}:*", group)];
+                targets.extend(members.iter().map(|member| format!("{}:{}", group, member)));
+                return targets;
+            }
+            return self.supervisor
+                .status()
+                .into_iter()
+                .filter(|program| program.name == prefix)
+                .flat_map(|program| program.instances.into_iter().map(move |instance| format!("{}:{}", program.name, instance.instance)))
+                .collect();
+        }
+        let mut targets = vec!["all".to_string()];
+        targets.extend(self.supervisor.program_names());
+        targets.extend(groups.into_iter().map(|(group, _)| format!("{}:", group)));
+        targets
+    }
+
+    fn candidates(&self, words: &[&str], word: &str) -> Vec<String> {
+        if word.starts_with("--") {
+            return FLAGS.iter().map(|flag| flag.to_string()).collect();
+        }
+        let Some(command) = words.first() else {
+            return COMMANDS.iter().map(|(name, _, _)| name.to_string()).chain(["exit".to_string(), "quit".to_string()]).collect();
+        };
+        match Self::argument(command, words.len()) {
+            Argument::Command => COMMANDS.iter().map(|(name, _, _)| name.to_string()).collect(),
+            Argument::Target => self.targets(word),
+            Argument::Program => self.supervisor.program_names(),
+            Argument::Signal => signals::names().map(str::to_string).collect(),
+            Argument::Reset => vec!["reset".to_string()],
+            Argument::Level => ["quiet", "normal", "verbose"].iter().map(|level| level.to_string()).collect(),
+            Argument::Nothing => Vec::new(),
+        }
+    }
+
+    fn is_valid(&self, command: &str, index: usize, word: &str) -> bool {
+        if word.starts_with("--") {
+            return FLAGS.contains(&word);
+        }
+        if index == 0 {
+            return matches!(word, "exit" | "quit") || COMMANDS.iter().any(|(name, _, _)| *name == word);
+        }
+        match Self::argument(command, index) {
+            Argument::Target => match split_instance(&self.supervisor, word) {
+                Ok((name, Some(instance))) => self.supervisor
+                    .status()
+                    .iter()
+                    .any(|program| program.name == name && (instance as usize) < program.instances.len()),
+                Ok((pattern, None)) => self.supervisor.resolve(pattern).is_ok(),
+                Err(_) => false,
+            },
+            Argument::Program => self.supervisor.program_names().iter().any(|name| name == word),
+            Argument::Signal => signals::parse(word).is_some(),
+            Argument::Level => Notifications::parse(word).is_some(),
+            _ => true,
+        }
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let before = &line[..pos];
+        let start = before.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &before[start..];
+        let words: Vec<&str> = before[..start].split_whitespace().filter(|word| !word.starts_with("--")).collect();
+        let candidates = self.candidates(&words, word).into_iter().filter(|candidate| candidate.starts_with(word)).collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<String> {
+        // Called whenever the line changes, so it is where the notifier
+        // learns whether it may print above the prompt.
+        self.notifier.set_typing(!line.is_empty());
+        if line.is_empty() || pos < line.len() {
+            return None;
+        }
+        let (start, candidates) = self.complete(line, pos, ctx).ok()?;
+        match candidates.as_slice() {
+            [candidate] if candidate.len() > pos - start => Some(candidate[pos - start..].to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl Highlighter for ShellHelper {
+    /// Marks unknown commands and targets, except for the word under the
+    /// cursor while it can still be completed into a valid one.
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        let mut highlighted = String::with_capacity(line.len());
+        let mut words: Vec<&str> = Vec::new();
+        let mut invalid = false;
+        let mut rest = line;
+        while !rest.is_empty() {
+            let spaces = rest.len() - rest.trim_start().len();
+            highlighted.push_str(&rest[..spaces]);
+            rest = &rest[spaces..];
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let word = &rest[..end];
+            let typing = line.len() - rest.len() + end == pos;
+            rest = &rest[end..];
+            if word.is_empty() {
+                break;
+            }
+            let command = words.first().copied().unwrap_or(word);
+            let valid = self.is_valid(command, words.len(), word)
+                || (typing && self.candidates(&words, word).iter().any(|candidate| candidate.starts_with(word)));
+            if valid {
+                highlighted.push_str(word);
+            } else {
+                invalid = true;
+                highlighted.push_str(&format!("\x1b[31m{}\x1b[0m", word));
+            }
+            if !word.starts_with("--") {
+                words.push(word);
+            }
+        }
+        if invalid {
+            Cow::Owned(highlighted)
+        } else {
+            Cow::Borrowed(line)
+        }
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("\x1b[2m{}\x1b[0m", hint))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        true
+    }
+}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
@@ -0,0 +1,128 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+use crate::supervisor::SupervisorError;
+
+/// How many finished jobs are kept for `jobs` and `wait`. Older ones are
+/// forgotten as new jobs are created.
+const FINISHED_JOBS: usize = 50;
+
+/// The outcome of a job: one result per program or instance it acted on.
+pub type JobResults = Vec<(String, Result<(), SupervisorError>)>;
+
+/// A failed result as kept by a finished job, so every waiter can read it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobError {
+    pub kind: &'static str,
+    pub message: String,
+}
+
+impl From<&SupervisorError> for JobError {
+    fn from(e: &SupervisorError) -> Self {
+        JobError { kind: e.kind(), message: e.to_string() }
+    }
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The results of a finished job, as handed to its waiters.
+pub type JobOutcome = Vec<(String, Result<(), JobError>)>;
+
+struct JobState {
+    done: bool,
+    ok: bool,
+    results: JobOutcome,
+}
+
+/// A handle to a command running in the background. Clones refer to the
+/// same job.
+#[derive(Clone)]
+pub struct JobHandle {
+    id: u32,
+    description: String,
+    state: Arc<(Mutex<JobState>, Condvar)>,
+}
+
+impl JobHandle {
+    fn new(id: u32, description: String) -> Self {
+        let state = JobState { done: false, ok: false, results: Vec::new() };
+        JobHandle { id, description, state: Arc::new((Mutex::new(state), Condvar::new())) }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Whether the job has finished.
+    pub fn is_done(&self) -> bool {
+        self.state.0.lock().unwrap().done
+    }
+
+    /// Whether the job has finished with every result a success.
+    pub fn succeeded(&self) -> bool {
+        let state = self.state.0.lock().unwrap();
+        state.done && state.ok
+    }
+
+    pub(crate) fn finish(&self, results: JobResults) {
+        let (state, finished) = &*self.state;
+        let mut state = state.lock().unwrap();
+        state.done = true;
+        state.ok = results.iter().all(|(_, result)| result.is_ok());
+        state.results = results.into_iter().map(|(name, result)| (name, result.map_err(|e| JobError::from(&e)))).collect();
+        finished.notify_all();
+    }
+
+    /// Blocks until the job has finished, up to `timeout` if given, and
+    /// returns its results. Every waiter gets the same ones.
+    pub fn wait(&self, timeout: Option<Duration>) -> Option<JobOutcome> {
+        let (state, finished) = &*self.state;
+        let state = state.lock().unwrap();
+        let state = match timeout {
+            Some(timeout) => finished.wait_timeout_while(state, timeout, |state| !state.done).unwrap().0,
+            None => finished.wait_while(state, |state| !state.done).unwrap(),
+        };
+        state.done.then(|| state.results.clone())
+    }
+}
+
+/// The jobs started on a supervisor: running ones, and the latest
+/// `FINISHED_JOBS` finished ones.
+#[derive(Default)]
+pub struct Jobs {
+    next_id: AtomicU32,
+    jobs: Mutex<Vec<JobHandle>>,
+}
+
+impl Jobs {
+    pub(crate) fn create(&self, description: String) -> JobHandle {
+        let job = JobHandle::new(self.next_id.fetch_add(1, Ordering::SeqCst) + 1, description);
+        let mut jobs = self.jobs.lock().unwrap();
+        let finished = jobs.iter().filter(|job| job.is_done()).count();
+        let mut excess = finished.saturating_sub(FINISHED_JOBS);
+        jobs.retain(|job| {
+            let forget = excess > 0 && job.is_done();
+            excess -= forget as usize;
+            !forget
+        });
+        jobs.push(job.clone());
+        job
+    }
+
+    pub fn list(&self) -> Vec<JobHandle> {
+        self.jobs.lock().unwrap().clone()
+    }
+
+    pub fn get(&self, id: u32) -> Option<JobHandle> {
+        self.jobs.lock().unwrap().iter().find(|job| job.id == id).cloned()
+    }
+}
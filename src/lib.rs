@@ -0,0 +1,16 @@
+pub mod config;
+pub mod daemons;
+pub mod events;
+pub mod jobs;
+pub mod logger;
+pub mod supervisor;
+pub mod signals;
+pub mod syslog;
+pub mod targets;
+mod commands;
+
+pub use config::{AutoRestart, Config, ConfigError, Notifications, Program, ShellConfig};
+pub use events::Event;
+pub use jobs::{JobError, JobHandle, JobOutcome, JobResults};
+pub use logger::{Level, Logger, LoggingConfig};
+pub use supervisor::{ExitStatus, InstanceStatus, ProcessState, ProgramStatus, ShutdownSummary, Supervisor, SupervisorError};
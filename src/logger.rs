@@ -1,31 +1,326 @@
-use std::fs::{OpenOptions, File};
+use std::fmt::{self, Display};
+use std::fs::{self, OpenOptions, File};
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Deserialize;
+use crate::syslog::{Syslog, SyslogConfig};
 
-pub struct Logger {
-    log_file: Arc<Mutex<File>>,
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Crit,
 }
 
-impl Logger {
-    pub fn new(log_file: &str) -> io::Result<Self> {
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+            Level::Crit => "CRIT",
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct LoggingConfig {
+    #[serde(default = "default_log_file")]
+    pub file: Option<String>,
+    #[serde(default = "default_level")]
+    pub level: Level,
+    #[serde(default = "default_format")]
+    pub format: Format,
+    #[serde(default)]
+    pub max_bytes: u64,
+    #[serde(default = "default_backups")]
+    pub backups: u32,
+    #[serde(default)]
+    pub syslog: Option<SyslogConfig>,
+}
+
+fn default_log_file() -> Option<String> {
+    Some("taskmaster.log".to_string())
+}
+
+fn default_level() -> Level {
+    Level::Info
+}
+
+fn default_format() -> Format {
+    Format::Text
+}
+
+fn default_backups() -> u32 {
+    5
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            file: default_log_file(),
+            level: default_level(),
+            format: default_format(),
+            max_bytes: 0,
+            backups: default_backups(),
+            syslog: None,
+        }
+    }
+}
+
+struct LogFile {
+    file: File,
+    path: PathBuf,
+    size: u64,
+    /// Whether the last rotation failed, so that a run of failures is
+    /// reported once.
+    rotate_failed: bool,
+}
+
+impl LogFile {
+    fn open(path: &Path) -> io::Result<Self> {
         let file = OpenOptions::new()
             .create(true)
             .append(true)
-            .open(log_file)?;
+            .open(path)?;
+        let size = file.metadata()?.len();
+        Ok(LogFile { file, path: path.to_path_buf(), size, rotate_failed: false })
+    }
+
+    fn backup_path(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    /// Shifts `taskmaster.log.N` to `.N+1`, dropping the oldest backup, and
+    /// starts a fresh file. With no backups the current file is truncated.
+    fn rotate(&mut self, backups: u32) -> io::Result<()> {
+        self.file.flush()?;
+        if backups == 0 {
+            self.file = File::create(&self.path)?;
+            self.size = 0;
+            return Ok(());
+        }
+        for index in (1..backups).rev() {
+            let from = self.backup_path(index);
+            if from.exists() {
+                fs::rename(&from, self.backup_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.backup_path(1))?;
+        let rotate_failed = self.rotate_failed;
+        *self = LogFile::open(&self.path)?;
+        self.rotate_failed = rotate_failed;
+        Ok(())
+    }
+}
+
+pub struct Logger {
+    log_file: Option<Arc<Mutex<LogFile>>>,
+    syslog: Option<Arc<Syslog>>,
+    /// Records syslog did not take. Only the first one is reported.
+    syslog_failures: AtomicU64,
+    output_syslog: Mutex<Option<Arc<Syslog>>>,
+    level: Level,
+    format: Format,
+    max_bytes: u64,
+    backups: u32,
+}
+
+impl Logger {
+    pub fn with_config(config: &LoggingConfig) -> io::Result<Self> {
+        let log_file = match config.file {
+            Some(ref path) => Some(Arc::new(Mutex::new(LogFile::open(Path::new(path))?))),
+            None => None,
+        };
+        let syslog = match config.syslog {
+            Some(ref syslog) => Some(Arc::new(Syslog::connect(syslog)?)),
+            None => None,
+        };
         Ok(Logger {
-            log_file: Arc::new(Mutex::new(file)),
+            log_file,
+            syslog,
+            syslog_failures: AtomicU64::new(0),
+            output_syslog: Mutex::new(None),
+            level: config.level,
+            format: config.format,
+            max_bytes: config.max_bytes,
+            backups: config.backups,
         })
     }
 
-    pub fn log(&self, message: &str) -> io::Result<()> {
-        let mut file = self.log_file.lock().unwrap();
-        writeln!(file, "{}", message)?;
+    /// Reopens the log file at its configured path, for use after an
+    /// external tool such as logrotate has moved it away.
+    pub fn reopen(&self) -> io::Result<()> {
+        let Some(ref log_file) = self.log_file else {
+            return Ok(());
+        };
+        let mut log_file = log_file.lock().unwrap();
+        log_file.file.flush()?;
+        let path = log_file.path.clone();
+        *log_file = LogFile::open(&path)?;
         Ok(())
     }
 
-	pub fn log_formatted(&self, format_str: &str, args: std::fmt::Arguments) -> io::Result<()> {
-        let mut file = self.log_file.lock().unwrap();
-        writeln!(file, "{}", format_args!("{} {}", format_str, args))?;
+    /// Returns the syslog connection used for programs' captured output: the
+    /// configured sink if there is one, otherwise a default `/dev/log` one
+    /// opened on first use.
+    pub fn output_syslog(&self) -> io::Result<Arc<Syslog>> {
+        if let Some(ref syslog) = self.syslog {
+            return Ok(Arc::clone(syslog));
+        }
+        let mut output_syslog = self.output_syslog.lock().unwrap();
+        if let Some(ref syslog) = *output_syslog {
+            return Ok(Arc::clone(syslog));
+        }
+        let syslog = Arc::new(Syslog::connect(&SyslogConfig::default())?);
+        *output_syslog = Some(Arc::clone(&syslog));
+        Ok(syslog)
+    }
+
+    pub fn enabled(&self, level: Level) -> bool {
+        level >= self.level
+    }
+
+    /// How many records could not be sent to syslog.
+    pub fn syslog_failures(&self) -> u64 {
+        self.syslog_failures.load(Ordering::Relaxed)
+    }
+
+    /// Writes one record made of a level, the emitting component, an event
+    /// type (`spawned`, `exited`, `fatal`...) and its key/value fields.
+    /// Syslog is best effort: the record still goes to the file when it
+    /// cannot be sent, and only the first failure is written there.
+    pub fn event(&self, level: Level, component: &str, event: &str, fields: &[(&str, &dyn Display)]) -> io::Result<()> {
+        if !self.enabled(level) {
+            return Ok(());
+        }
+        let timestamp = rfc3339(SystemTime::now());
+        let body = self.body(&timestamp, level, component, event, fields);
+        let syslog_error = self
+            .syslog
+            .as_ref()
+            .and_then(|syslog| syslog.send(level.into(), syslog.ident(), std::process::id(), event, &body).err());
+        self.write_file(&timestamp, level, body)?;
+        if let Some(e) = syslog_error {
+            if self.syslog_failures.fetch_add(1, Ordering::Relaxed) == 0 {
+                let body = self.body(&timestamp, Level::Error, "logger", "syslog_failed", &[("error", &e), ("further", &"counted")]);
+                self.write_file(&timestamp, Level::Error, body)?;
+            }
+        }
         Ok(())
     }
+
+    fn body(&self, timestamp: &str, level: Level, component: &str, event: &str, fields: &[(&str, &dyn Display)]) -> String {
+        match self.format {
+            Format::Text => format_text(component, event, fields),
+            Format::Json => format_json(timestamp, level, component, event, fields),
+        }
+    }
+
+    fn line(&self, timestamp: &str, level: Level, body: String) -> String {
+        match self.format {
+            Format::Text => format!("{} {:<5} taskmaster[{}] {}", timestamp, level, std::process::id(), body),
+            Format::Json => body,
+        }
+    }
+
+    /// Appends a record to the log file, rotating it first when it would
+    /// grow past `max_bytes`. When rotation fails the record still goes to
+    /// the current file, the first failure of a run is written there too,
+    /// and rotation is tried again on the next record.
+    fn write_file(&self, timestamp: &str, level: Level, body: String) -> io::Result<()> {
+        let Some(ref log_file) = self.log_file else {
+            return Ok(());
+        };
+        let line = self.line(timestamp, level, body);
+        let mut log_file = log_file.lock().unwrap();
+        let len = line.len() as u64 + 1;
+        if self.max_bytes > 0 && log_file.size > 0 && log_file.size + len > self.max_bytes {
+            match log_file.rotate(self.backups) {
+                Ok(()) => log_file.rotate_failed = false,
+                Err(e) if !log_file.rotate_failed => {
+                    log_file.rotate_failed = true;
+                    let body = self.body(timestamp, Level::Error, "logger", "rotate_failed", &[("error", &e), ("further", &"retried")]);
+                    let report = self.line(timestamp, Level::Error, body);
+                    writeln!(log_file.file, "{}", report)?;
+                    log_file.size += report.len() as u64 + 1;
+                }
+                Err(_) => {}
+            }
+        }
+        writeln!(log_file.file, "{}", line)?;
+        log_file.size += len;
+        Ok(())
+    }
+}
+
+fn format_text(component: &str, event: &str, fields: &[(&str, &dyn Display)]) -> String {
+    let mut line = format!("{}: {}", component, event);
+    for (key, value) in fields {
+        let value = value.to_string();
+        if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+            line.push_str(&format!(" {}={:?}", key, value));
+        } else {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+    }
+    line
+}
+
+fn format_json(timestamp: &str, level: Level, component: &str, event: &str, fields: &[(&str, &dyn Display)]) -> String {
+    let fields: serde_json::Map<String, serde_json::Value> = fields
+        .iter()
+        .map(|(key, value)| (key.to_string(), serde_json::Value::String(value.to_string())))
+        .collect();
+    serde_json::json!({
+        "ts": timestamp,
+        "level": level.as_str(),
+        "pid": std::process::id(),
+        "component": component,
+        "event": event,
+        "fields": fields,
+    })
+    .to_string()
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp with millisecond precision.
+pub fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::gmtime_r(&secs, &mut tm);
+    }
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        since_epoch.subsec_millis()
+    )
 }
@@ -1,54 +1,104 @@
+mod helper;
+mod notifier;
 mod shell;
-mod daemons;
-mod commands;
-mod logger;
 
-use std::fs;
-use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
-use std::process::Child;
-use serde::Deserialize;
-use std::time::Instant;
-use crate::logger::Logger;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::mem::MaybeUninit;
+use std::process;
+use std::sync::Arc;
+use taskmaster::{daemons, Config, Logger, ShutdownSummary, Supervisor};
+use crate::shell::Outcome;
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
-pub struct Program {
-    cmd: String,
-    numprocs: u32,
-    umask: String,
-    workingdir: String,
-    autostart: bool,
-    autorestart: String,
-    exitcodes: Vec<i32>,
-    startretries: u32,
-    starttime: u32,
-    stopsignal: i32,
-    stoptime: u32,
-    stdout: String,
-    stderr: String,
-    env: Option<HashMap<String, String>>,
+/// Runs the interactive shell on a terminal. Otherwise the commands come
+/// from the arguments (`taskmaster status --json`), from a file given with
+/// `-f`, or from stdin. Either way the programs are shut down gracefully at
+/// the end, or on SIGTERM or SIGINT. The exit code is 0 when everything
+/// succeeded, 1 when a command failed and 2 when the shutdown had to
+/// SIGKILL instances.
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let interactive = args.is_empty() && unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+    if interactive {
+        println!("Taskmaster");
+    }
+    let config = match Config::from_file("config.yml") {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+	let logger = Arc::new(Logger::with_config(&config.logging).expect("Failed to create logger"));
+    let notifications = config.shell.notifications;
+    let supervisor = Supervisor::new(config, logger);
+    // Subscribed before autostart so the shell also shows its failures.
+    let events = interactive.then(|| supervisor.subscribe());
+    let terminal = if interactive { terminal_mode() } else { None };
+    daemons::start(supervisor.clone(), move |summary| {
+        // The line editor may have the terminal in raw mode.
+        if let Some(terminal) = &terminal {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, terminal) };
+            print!("\x1b[?2004l\r\n");
+            report_shutdown(&summary);
+            println!("Bye");
+        }
+        process::exit(exit_code(true, &summary));
+    });
+    // After the monitor is up, which dependents wait on. Commands that are
+    // not typed in see the programs up, as they would after `start`.
+    let autostart = supervisor.autostart_programs();
+    if let (false, Some(job)) = (interactive, autostart) {
+        job.wait(None);
+    }
+    let ok = match (args.as_slice(), events) {
+        ([], Some(events)) => {
+            shell::start(&supervisor, events, notifications);
+            true
+        }
+        ([], None) => shell::run_script(&supervisor, io::stdin().lock()),
+        ([flag, path], _) if flag == "-f" || flag == "--file" => match File::open(path) {
+            Ok(file) => shell::run_script(&supervisor, BufReader::new(file)),
+            Err(e) => {
+                eprintln!("Failed to open {}: {}", path, e);
+                false
+            }
+        },
+        (command, _) => !matches!(shell::execute(&supervisor, None, &command.join(" ")), Outcome::Done(false)),
+    };
+    if interactive {
+        println!("Shutting down...");
+    }
+    let summary = supervisor.shutdown();
+    if interactive {
+        report_shutdown(&summary);
+        println!("Bye");
+    }
+    process::exit(exit_code(ok, &summary));
 }
 
-pub struct ProcessInfo {
-    pub child: Child,
-    pub start_time: Instant,
-    pub time_elapsed_since_stop: Option<Instant>,
-	pub successfully_started: bool,
-	pub stopped_by_signal: bool
+/// The terminal settings of stdin, to restore them when exiting on a
+/// signal.
+fn terminal_mode() -> Option<libc::termios> {
+    let mut termios = MaybeUninit::uninit();
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    Some(unsafe { termios.assume_init() })
 }
 
-fn parsing() -> HashMap<String, Program> {
-    let config = fs::read_to_string("config.yml").expect("Failed to read config file");
-	serde_yaml::from_str(&config).expect("Failed to parse config")
+fn report_shutdown(summary: &ShutdownSummary) {
+    println!("Stopped {} instance(s), killed {} after their stoptime", summary.stopped, summary.killed);
+    let _ = io::stdout().flush();
 }
 
-fn main() {
-    println!("Taskmaster");
-	let logger = Arc::new(Logger::new("taskmaster.log").expect("Failed to create logger"));
-    let processes = Arc::new(Mutex::new(HashMap::<String, Vec<ProcessInfo>>::new()));
-    let programs = Arc::new(Mutex::new(parsing()));
-    commands::autostart_programs(&programs, &processes, &logger);
-    daemons::start(programs.clone(), processes.clone(), logger.clone());
-    shell::start(programs, processes, logger);
-    println!("Bye");
+fn exit_code(ok: bool, summary: &ShutdownSummary) -> i32 {
+    if !summary.is_clean() {
+        2
+    } else if !ok {
+        1
+    } else {
+        0
+    }
 }
@@ -0,0 +1,194 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use taskmaster::{Event, Notifications, Supervisor};
+use crate::shell::{transition, PROMPT};
+
+/// How many messages are held back while a line is being typed. Older
+/// ones are dropped past that, and only counted.
+const QUEUED_MESSAGES: usize = 100;
+
+/// Shows events as they happen. While the prompt is up with an empty line
+/// they are printed above it and the prompt is drawn again. While a line
+/// is being typed they are queued instead, so that they never break into
+/// it, and shown once the line is entered or emptied. The line editor's
+/// external printer would do this for any line, but it stalls on typeahead.
+pub struct Notifier {
+    level: Mutex<Notifications>,
+    prompt: Mutex<Prompt>,
+    /// Instances that commands in the foreground report on themselves.
+    followed: Mutex<Vec<Follow>>,
+    next_id: Mutex<u32>,
+}
+
+#[derive(Default)]
+struct Prompt {
+    up: bool,
+    /// Whether the line at the prompt has anything typed in it.
+    typing: bool,
+    queued: VecDeque<String>,
+    dropped: usize,
+}
+
+impl Prompt {
+    /// Prints the queued messages, above the prompt if it is up.
+    fn flush(&mut self) {
+        if self.queued.is_empty() && self.dropped == 0 {
+            return;
+        }
+        if self.up {
+            print!("\r\x1b[K");
+        }
+        if self.dropped > 0 {
+            println!("({} earlier notifications dropped)", self.dropped);
+            self.dropped = 0;
+        }
+        for message in self.queued.drain(..) {
+            print!("{}", message);
+        }
+        if self.up {
+            print!("{}", PROMPT);
+        }
+        let _ = io::stdout().flush();
+    }
+}
+
+struct Follow {
+    id: u32,
+    targets: Vec<(String, Option<u32>)>,
+    done: bool,
+}
+
+impl Notifier {
+    /// Shows the events of `events` at `level` from a thread of its own
+    /// until the supervisor goes away.
+    pub fn start(supervisor: Supervisor, events: Receiver<Event>, level: Notifications) -> Arc<Self> {
+        let notifier = Arc::new(Notifier {
+            level: Mutex::new(level),
+            prompt: Mutex::new(Prompt::default()),
+            followed: Mutex::new(Vec::new()),
+            next_id: Mutex::new(0),
+        });
+        let shown = Arc::clone(&notifier);
+        thread::spawn(move || loop {
+            // A command that finished before this round has had all of
+            // its events queued, so its entry can go once they are drained.
+            let finished: Vec<u32> = shown.followed.lock().unwrap().iter().filter(|follow| follow.done).map(|follow| follow.id).collect();
+            match events.recv_timeout(Duration::from_millis(200)) {
+                Ok(event) => {
+                    for event in [event].into_iter().chain(events.try_iter()) {
+                        if let Some(message) = shown.message(&supervisor, &event) {
+                            shown.show(message);
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            let mut prompt = shown.prompt.lock().unwrap();
+            if !prompt.typing {
+                prompt.flush();
+            }
+            drop(prompt);
+            shown.followed.lock().unwrap().retain(|follow| !finished.contains(&follow.id));
+        });
+        notifier
+    }
+
+    fn show(&self, message: String) {
+        let mut prompt = self.prompt.lock().unwrap();
+        if prompt.queued.len() == QUEUED_MESSAGES {
+            prompt.queued.pop_front();
+            prompt.dropped += 1;
+        }
+        prompt.queued.push_back(message);
+        if !prompt.typing {
+            prompt.flush();
+        }
+    }
+
+    /// Marks the prompt as up or gone, printing what was queued first.
+    pub fn set_prompt(&self, up: bool) {
+        let mut prompt = self.prompt.lock().unwrap();
+        prompt.up = false;
+        prompt.typing = false;
+        prompt.flush();
+        prompt.up = up;
+    }
+
+    /// Tells whether the line at the prompt has anything typed in it.
+    /// Messages wait until it is entered or emptied.
+    pub fn set_typing(&self, typing: bool) {
+        self.prompt.lock().unwrap().typing = typing;
+    }
+
+    pub fn level(&self) -> Notifications {
+        *self.level.lock().unwrap()
+    }
+
+    pub fn set_level(&self, level: Notifications) {
+        *self.level.lock().unwrap() = level;
+    }
+
+    /// Stops showing the events of `targets`, each a program or one of its
+    /// instances, until `unfollow` is called with the returned id.
+    pub fn follow(&self, targets: Vec<(String, Option<u32>)>) -> u32 {
+        let mut next_id = self.next_id.lock().unwrap();
+        *next_id += 1;
+        self.followed.lock().unwrap().push(Follow { id: *next_id, targets, done: false });
+        *next_id
+    }
+
+    pub fn unfollow(&self, id: u32) {
+        if let Some(follow) = self.followed.lock().unwrap().iter_mut().find(|follow| follow.id == id) {
+            follow.done = true;
+        }
+    }
+
+    fn is_followed(&self, program: &str, instance: u32) -> bool {
+        self.followed
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|follow| &follow.targets)
+            .any(|(name, only)| name == program && only.is_none_or(|only| only == instance))
+    }
+
+    /// The line to show for `event`, if the level lets it through.
+    fn message(&self, supervisor: &Supervisor, event: &Event) -> Option<String> {
+        if required_level(event) > self.level() {
+            return None;
+        }
+        match event {
+            Event::Reloaded => {
+                let mut message = "config reloaded\n".to_string();
+                for program in supervisor.status() {
+                    if let Some(numprocs) = program.numprocs_override {
+                        message.push_str(&format!("{}: keeps its runtime numprocs {} (config says {})\n", program.name, numprocs, program.program.numprocs));
+                    }
+                }
+                Some(message)
+            }
+            Event::ReloadFailed { error } => Some(format!("reload failed: {}\n", error)),
+            Event::DependencyFatal { program, dependency } => Some(format!("{}: dependency {} is FATAL\n", program, dependency)),
+            _ => {
+                let (program, instance, transition) = transition(event)?;
+                (!self.is_followed(program, instance)).then(|| format!("{}:{} {}\n", program, instance, transition))
+            }
+        }
+    }
+}
+
+/// The least verbose level that shows `event`.
+fn required_level(event: &Event) -> Notifications {
+    match event {
+        Event::SpawnFailed { .. } | Event::Killed { .. } | Event::Fatal { .. } | Event::ReloadFailed { .. } => Notifications::Quiet,
+        Event::DependencyFatal { .. } => Notifications::Quiet,
+        Event::Exited { expected: false, .. } => Notifications::Quiet,
+        Event::Running { .. } | Event::Exited { .. } | Event::Backoff { .. } | Event::Stopped { .. } | Event::Reloaded => Notifications::Normal,
+        Event::Spawned { .. } | Event::Stopping { .. } | Event::Paused { .. } | Event::Resumed { .. } => Notifications::Verbose,
+    }
+}
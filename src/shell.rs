@@ -1,177 +1,133 @@
-use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
-use std::time::Instant;
 use rustyline::{Editor, error::ReadlineError};
-use crate::Program;
-use crate::commands::start_program;
-use crate::logger::Logger;
-use crate::ProcessInfo;
-use crate::commands::check_running_time;
-
-pub fn start(programs: Arc<Mutex<HashMap<String, Program>>>, processes: Arc<Mutex<HashMap<String, Vec<ProcessInfo>>>>, logger: Arc<Logger>) {
-    let mut rl = Editor::<()>::new().expect("Failed to create line editor");
+use serde_json::json;
+use std::env;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use taskmaster::logger::rfc3339;
+use taskmaster::signals;
+use crate::helper::ShellHelper;
+use crate::notifier::Notifier;
+use taskmaster::{Event, InstanceStatus, JobError, JobHandle, JobOutcome, JobResults, Notifications, ProgramStatus, Supervisor, SupervisorError};
+
+/// What the interactive shell shows when it waits for a command.
+pub const PROMPT: &str = "> ";
+
+/// Every shell command with its usage and a one-line description, in the
+/// order `help` lists them.
+pub const COMMANDS: &[(&str, &str, &str)] = &[
+    ("status", "status [target] [--groups] [--json]", "Show each instance, optionally of one program, group or instance."),
+    ("start", "start <target>... [--no-wait] [--json]", "Start programs that are not running and wait until they are RUNNING or failed."),
+    ("stop", "stop <target>... [--no-wait] [--json]", "Stop programs with their stopsignal, then SIGKILL after stoptime."),
+    ("restart", "restart <target>... [--no-wait] [--json]", "Stop programs if they are running, then start them again."),
+    ("pause", "pause <target>... [--no-wait] [--json]", "Freeze running instances with SIGSTOP."),
+    ("resume", "resume <target>... [--no-wait] [--json]", "Thaw paused instances with SIGCONT."),
+    ("signal", "signal <SIGNAME> <target>... [--no-wait] [--json]", "Send a signal to running instances without changing their state."),
+    ("reset", "reset <target>... [--no-wait] [--json]", "Clear FATAL instances back to STOPPED, with their restart history, so they can be started."),
+    ("scale", "scale <program> <numprocs|reset> [--no-wait] [--json]", "Run another number of instances until reset, even across reloads."),
+    ("jobs", "jobs [--json]", "List the jobs started by commands and whether they finished."),
+    ("wait", "wait [job]... [--json]", "Wait for jobs, or every running one, and show their results."),
+    ("notify", "notify [quiet|normal|verbose]", "Show or set which events the shell shows as they happen."),
+    ("help", "help [command]", "List the commands, or show the usage of one."),
+    ("shutdown", "shutdown", "Stop every program in reverse start order, then leave. Also exit and quit."),
+];
+
+/// Describes the targets taken by the commands that accept them.
+const TARGETS: &str = "A target is a program, a glob such as web-*, all, a group member as group:program, every member as group:*, or a single instance as program:index.";
+
+/// Describes the job taken by the commands that run as one.
+const JOBS: &str = "The command runs as a job and waits for it, printing the transitions; with --no-wait it returns the job number at once, see jobs and wait.";
+
+/// How a command line ended.
+pub enum Outcome {
+    /// The command ran, successfully or not.
+    Done(bool),
+    /// The command asked to leave the shell.
+    Exit,
+}
+
+/// Runs one command line, printing its output. In the interactive shell,
+/// `notifier` shows events the command does not report itself.
+pub fn execute(supervisor: &Supervisor, notifier: Option<&Notifier>, line: &str) -> Outcome {
+    let (flags, cmd): (Vec<&str>, Vec<&str>) = line.split_whitespace().partition(|word| word.starts_with("--"));
+    let json = flags.contains(&"--json");
+    let wait = !flags.contains(&"--no-wait");
+    if cmd.is_empty() {
+        return Outcome::Done(true);
+    }
+    let ok = match cmd[0] {
+        "shutdown" | "exit" | "quit" => return Outcome::Exit,
+        "status" => print_status(supervisor, cmd.get(1).copied(), json, flags.contains(&"--groups")),
+        "start" | "stop" | "restart" | "pause" | "resume" | "reset" => {
+            if cmd.len() < 2 {
+                return Outcome::Done(report_error(json, cmd[0], "missing_argument", &format!("Please specify a program to {}", cmd[0])));
+            }
+            let action = match cmd[0] {
+                "start" => Action::Start,
+                "stop" => Action::Stop,
+                "restart" => Action::Restart,
+                "pause" => Action::Pause,
+                "resume" => Action::Resume,
+                _ => Action::Reset,
+            };
+            run_batch(supervisor, notifier, action, &cmd.join(" "), &cmd[1..], wait, json)
+        }
+        "signal" => {
+            if cmd.len() < 3 {
+                return Outcome::Done(report_error(json, "signal", "missing_argument", "Usage: signal <SIGNAME> <program|group|all>..."));
+            }
+            let Some(signal) = signals::parse(cmd[1]) else {
+                return Outcome::Done(report_error(json, "signal", "invalid_argument", &format!("Unknown signal {}", cmd[1])));
+            };
+            run_batch(supervisor, notifier, Action::Signal(signal), &cmd.join(" "), &cmd[2..], wait, json)
+        }
+        "scale" => {
+            let numprocs = match cmd.get(2).copied() {
+                Some("reset") => None,
+                Some(count) => match count.parse::<u32>() {
+                    Ok(numprocs) => Some(numprocs),
+                    Err(_) => return Outcome::Done(report_error(json, "scale", "invalid_argument", &format!("Invalid instance count {:?}", count))),
+                },
+                None => return Outcome::Done(report_error(json, "scale", "missing_argument", "Usage: scale <program> <numprocs|reset>")),
+            };
+            if cmd.len() > 3 || matches!(split_instance(supervisor, cmd[1]), Ok((_, Some(_)))) {
+                return Outcome::Done(report_error(json, "scale", "invalid_argument", "Usage: scale <program> <numprocs|reset>"));
+            }
+            run_batch(supervisor, notifier, Action::Scale(numprocs), &cmd.join(" "), &cmd[1..2], wait, json)
+        }
+        "jobs" => list_jobs(supervisor, json),
+        "wait" => wait_jobs(supervisor, &cmd[1..], json),
+        "notify" => notify(notifier, cmd.get(1).copied()),
+        "help" => help(cmd.get(1).copied()),
+        _ => report_error(json, cmd[0], "unknown_command", &format!("Unknown command {}, see help", cmd[0])),
+    };
+    Outcome::Done(ok)
+}
+
+/// Reads commands from the terminal until `shutdown` or end of input,
+/// keeping the history in `~/.taskmaster_history` across sessions. The
+/// events of `events` are shown at the `notifications` level, above the
+/// prompt, or once the line is entered if one is being typed.
+pub fn start(supervisor: &Supervisor, events: Receiver<Event>, notifications: Notifications) {
+    let notifier = Notifier::start(supervisor.clone(), events, notifications);
+    let mut rl = Editor::<ShellHelper>::new().expect("Failed to create line editor");
+    rl.set_helper(Some(ShellHelper::new(supervisor.clone(), Arc::clone(&notifier))));
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(".taskmaster_history"));
+    if let Some(history) = &history {
+        let _ = rl.load_history(history);
+    }
     loop {
-        match rl.readline("> ") {
+        notifier.set_prompt(true);
+        let line = rl.readline(PROMPT);
+        notifier.set_prompt(false);
+        match line {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
-                let cmd: Vec<&str> = line.trim().split_whitespace().collect();
-                if cmd.is_empty() {
-                    continue;
-                }
-                let mut processes = processes.lock().unwrap();
-                let programs = programs.lock().unwrap();
-                match cmd[0] {
-                    "exit" | "quit" => break,
-                    "status" => {
-                        for program_name in programs.keys() {
-                            if let Some(instances) = processes.get(program_name) {
-                                if instances.is_empty() {
-                                    println!("{} status: exited", program_name);
-                                } else {
-                                    println!("{} status: running ({} instances)", program_name, instances.len());
-                                }
-                            } else {
-                                println!("{} status: not running", program_name);
-                            }
-                        }
-                    }
-					"start" => {
-						if cmd.len() < 2 {
-							println!("Please specify a program to start");
-							continue;
-						}
-						let program_name = cmd[1].to_string();
-						if processes.contains_key(&program_name) {
-							println!("Program {} is already running", program_name);
-							continue;
-						}
-						let program = match programs.get(&program_name) {
-							Some(program) => program,
-							None => {
-								println!("Program not found");
-								continue;
-							}
-						};
-
-						let mut instances = Vec::new();
-						for i in 0..program.numprocs {
-							let mut attempts = 0;
-							while attempts < program.startretries {
-								match start_program(program) {
-									Ok(process_info) => {
-										instances.push(process_info);
-										if let Some(last_process_info) = instances.last_mut() {
-											check_running_time(&program_name, last_process_info, program.starttime.into(), &logger);
-										}
-										logger.log_formatted("Started", format_args!("{} instance {}", program_name, i))
-											.expect("Failed to log message");
-										println!("Started {} instance {}", program_name, i);
-										break;
-									}
-									Err(e) => {
-										eprintln!("Failed to start {} instance {}: {}", program_name, i, e);
-										attempts += 1;
-										if attempts < program.startretries {
-											logger.log_formatted("Retry", format_args!("Retrying to start {} instance {} (attempt {}/{})", program_name, i, attempts + 1, program.startretries))
-												.expect("Failed to log message");
-											eprintln!("Retrying to start {} instance {} (attempt {}/{})", program_name, i, attempts + 1, program.startretries);
-										}
-									}
-								}
-							}
-						}
-						if !instances.is_empty() {
-							processes.insert(program_name, instances);
-						}
-					}
-                    "stop" => {
-                        if cmd.len() < 2 {
-                            println!("Please specify a program to stop");
-                            continue;
-                        }
-                        let program_name = cmd[1].to_string();
-                        if let Some(mut instances) = processes.remove(&program_name) {
-                            for (i, mut process_info) in instances.drain(..).enumerate() {
-                                match process_info.child.kill() {
-                                    Ok(_) => {
-                                        logger.log_formatted("Stopped", format_args!("{} instance {}", program_name, i))
-                                            .expect("Failed to log message");
-                                        println!("Stopped {} instance {}", program_name, i);
-										process_info.time_elapsed_since_stop = Some(Instant::now());
-                                    }
-                                    Err(e) => {
-                                        eprintln!("Failed to stop {} instance {}: {}", program_name, i, e);
-                                        processes.entry(program_name.clone()).or_insert_with(Vec::new).push(process_info);
-                                    }
-                                }
-                            }
-                        } else {
-                            println!("Program not found or not running");
-                        }
-                    }
-					"restart" => {
-						if cmd.len() < 2 {
-							println!("Please specify a program to restart");
-							continue;
-						}
-						let program_name = cmd[1].to_string();
-						match processes.remove(&program_name) {
-							Some(instances) => {
-								for (i, mut instance) in instances.into_iter().enumerate() {
-									let _ = instance.child.kill();
-									let _ = instance.child.wait();
-									logger.log_formatted("Stopped", format_args!("{} instance {}", program_name, i))
-										.expect("Failed to log message");
-									println!("Stopped {} instance {}", program_name, i);
-								}
-
-								let program = match programs.get(&program_name) {
-									Some(program) => program,
-									None => {
-										println!("Program not found");
-										continue;
-									}
-								};
-
-								let mut new_instances = Vec::new();
-								for i in 0..program.numprocs {
-									let mut attempts = 0;
-									while attempts < program.startretries {
-										match start_program(program) {
-											Ok(process_info) => {
-												new_instances.push(process_info);
-												if let Some(last_process_info) = new_instances.last_mut() {
-													check_running_time(&program_name, last_process_info, program.starttime.into(), &logger);
-												}
-												logger.log_formatted("Started", format_args!("{} instance {}", program_name, i))
-													.expect("Failed to log message");
-												println!("Started {} instance {}", program_name, i);
-												break;
-											}
-											Err(e) => {
-												eprintln!("Failed to start {} instance {}: {}", program_name, i, e);
-												attempts += 1;
-												if attempts < program.startretries {
-													logger.log_formatted("Retry", format_args!("Retrying to restart {} instance {} (attempt {}/{})", program_name, i, attempts + 1, program.startretries))
-														.expect("Failed to log message");
-													eprintln!("Retrying to restart {} instance {} (attempt {}/{})", program_name, i, attempts + 1, program.startretries);
-												}
-											}
-										}
-									}
-								}
-								if !new_instances.is_empty() {
-									processes.insert(program_name, new_instances);
-								}
-							}
-							None => {
-								println!("Program not running");
-							}
-						}
-					}
-                    _ => {
-                        println!("Unknown command");
-                    }
+                if let Outcome::Exit = execute(supervisor, Some(&notifier), &line) {
+                    break;
                 }
             },
             Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {
@@ -183,18 +139,485 @@ pub fn start(programs: Arc<Mutex<HashMap<String, Program>>>, processes: Arc<Mute
             }
         }
     }
+    if let Some(history) = &history {
+        if let Err(e) = rl.save_history(history) {
+            eprintln!("Failed to save history to {}: {}", history.display(), e);
+        }
+    }
+}
+
+/// Runs every line of `reader` as a command, skipping blank lines and
+/// `#` comments, until `shutdown` or the end. Returns whether every command
+/// succeeded.
+pub fn run_script<R: BufRead>(supervisor: &Supervisor, reader: R) -> bool {
+    let mut ok = true;
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Failed to read command: {}", e);
+                return false;
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match execute(supervisor, None, line) {
+            Outcome::Done(done) => ok &= done,
+            Outcome::Exit => break,
+        }
+    }
+    ok
+}
+
+fn help(command: Option<&str>) -> bool {
+    match command {
+        None => {
+            for (_, usage, about) in COMMANDS {
+                println!("  {:<50} {}", usage, about);
+            }
+            println!("{}", TARGETS);
+            true
+        }
+        Some(command) => match COMMANDS.iter().find(|(name, _, _)| *name == command || (matches!(command, "exit" | "quit") && *name == "shutdown")) {
+            Some((_, usage, about)) => {
+                println!("Usage: {}", usage);
+                println!("{}", about);
+                if usage.contains("<target>") {
+                    println!("{}", TARGETS);
+                }
+                if usage.contains("--no-wait") {
+                    println!("{}", JOBS);
+                }
+                true
+            }
+            None => {
+                println!("Unknown command {}, see help", command);
+                false
+            }
+        },
+    }
+}
+
+/// What a batch command does to each of its targets.
+#[derive(Clone, Copy)]
+enum Action {
+    Start,
+    Stop,
+    Restart,
+    Pause,
+    Resume,
+    Signal(i32),
+    Reset,
+    /// A `numprocs` override, or `None` to drop it.
+    Scale(Option<u32>),
+}
 
-    let mut processes = processes.lock().unwrap();
-    for (program_name, mut instances) in processes.drain() {
-        for (i, mut process_info) in instances.drain(..).enumerate() {
-            match process_info.child.kill() {
-                Ok(_) => {
-                    logger.log_formatted("Killed", format_args!("{} instance {}", program_name, i))
-                        .expect("Failed to log message");
-                    println!("Killed {} instance {}", program_name, i);
+impl Action {
+    fn command(&self) -> &'static str {
+        match self {
+            Action::Start => "start",
+            Action::Stop => "stop",
+            Action::Restart => "restart",
+            Action::Pause => "pause",
+            Action::Resume => "resume",
+            Action::Signal(_) => "signal",
+            Action::Reset => "reset",
+            Action::Scale(_) => "scale",
+        }
+    }
+
+    /// Runs the action on a program, or on one of its instances.
+    fn run(&self, supervisor: &Supervisor, name: &str, instance: Option<u32>) -> Result<(), SupervisorError> {
+        match (self, instance) {
+            (Action::Start, None) => supervisor.start_program(name),
+            (Action::Start, Some(instance)) => supervisor.start_program_instance(name, instance),
+            (Action::Stop, None) => supervisor.stop_program(name),
+            (Action::Stop, Some(instance)) => supervisor.stop_program_instance(name, instance),
+            (Action::Restart, None) => supervisor.restart_program(name),
+            (Action::Restart, Some(instance)) => supervisor.restart_program_instance(name, instance),
+            (Action::Pause, None) => supervisor.pause_program(name),
+            (Action::Pause, Some(instance)) => supervisor.pause_program_instance(name, instance),
+            (Action::Resume, None) => supervisor.resume_program(name),
+            (Action::Resume, Some(instance)) => supervisor.resume_program_instance(name, instance),
+            (Action::Signal(signal), None) => supervisor.signal_program(name, *signal),
+            (Action::Signal(signal), Some(instance)) => supervisor.signal_program_instance(name, instance, *signal),
+            (Action::Reset, None) => supervisor.reset_program(name),
+            (Action::Reset, Some(instance)) => supervisor.reset_program_instance(name, instance),
+            // Instance targets are refused before the job starts.
+            (Action::Scale(Some(numprocs)), _) => supervisor.scale(name, *numprocs),
+            (Action::Scale(None), _) => supervisor.reset_scale(name),
+        }
+    }
+
+    fn done(&self) -> String {
+        match self {
+            Action::Start => "started".to_string(),
+            Action::Stop => "stopped".to_string(),
+            Action::Restart => "restarted".to_string(),
+            Action::Pause => "paused".to_string(),
+            Action::Resume => "resumed".to_string(),
+            Action::Signal(signal) => format!("sent {}", signals::name(*signal)),
+            Action::Reset => "reset".to_string(),
+            Action::Scale(Some(numprocs)) => format!("scaled to {}", numprocs),
+            Action::Scale(None) => "back to its configured numprocs".to_string(),
+        }
+    }
+}
+
+/// Runs `action` once on every program the targets expand to, in
+/// start order, then on each `program:index` target, as a job. Starts,
+/// restarts and scales last until the instances are RUNNING or have failed. With
+/// `wait` the transitions are printed as they happen, followed by one
+/// result per target; otherwise only the job is reported. A failing target
+/// does not stop the others. Returns whether every target succeeded.
+fn run_batch(supervisor: &Supervisor, notifier: Option<&Notifier>, action: Action, description: &str, targets: &[&str], wait: bool, json: bool) -> bool {
+    let command = action.command();
+    let mut ok = true;
+    let mut names = Vec::new();
+    let mut instances = Vec::new();
+    for target in targets {
+        match split_instance(supervisor, target) {
+            Ok((name, Some(index))) => {
+                instances.push((name.to_string(), index));
+                continue;
+            }
+            Ok((_, None)) => {}
+            Err((kind, message)) => {
+                ok = report_error(json, command, kind, &format!("{}: {}", target, message));
+                continue;
+            }
+        }
+        match supervisor.resolve(target) {
+            Ok(resolved) => {
+                for name in resolved {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+            }
+            Err(e) => ok &= report_results(action, vec![(target.to_string(), Err(JobError::from(&e)))], json),
+        }
+    }
+    if names.is_empty() && instances.is_empty() {
+        return ok;
+    }
+    let mut followed: Vec<(String, Option<u32>)> = names.iter().map(|name| (name.clone(), None)).collect();
+    followed.extend(instances.iter().map(|(name, index)| (name.clone(), Some(*index))));
+    let events = supervisor.subscribe();
+    let follow = wait.then(|| notifier.map(|notifier| notifier.follow(followed.clone()))).flatten();
+    let job = supervisor.spawn_job(description, move |supervisor| run_action(supervisor, action, &names, &instances));
+    if !wait {
+        if json {
+            println!("{}", json!({ "command": command, "ok": true, "job": job.id() }));
+        } else {
+            println!("[job {}] {}", job.id(), job.description());
+        }
+        return ok;
+    }
+    let follows = |program: &str, instance: u32| {
+        followed.iter().any(|(name, only)| name == program && only.is_none_or(|only| only == instance))
+    };
+    loop {
+        let done = job.is_done();
+        for event in events.try_iter() {
+            if let Some((program, instance, transition)) = transition(&event) {
+                if !json && matches!(action, Action::Start | Action::Stop | Action::Restart | Action::Scale(_)) && follows(program, instance) {
+                    println!("{}:{} {}", program, instance, transition);
                 }
-                Err(e) => eprintln!("Failed to kill {} instance {}: {}", program_name, i, e),
             }
         }
+        if done {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
     }
+    if let (Some(notifier), Some(follow)) = (notifier, follow) {
+        notifier.unfollow(follow);
+    }
+    ok & report_results(action, job.wait(None).unwrap_or_default(), json)
+}
+
+/// The body of a batch job. Instances that were started are awaited until
+/// they are RUNNING or have failed, and count as failed in the latter case.
+fn run_action(supervisor: &Supervisor, action: Action, names: &[String], instances: &[(String, u32)]) -> JobResults {
+    let events = supervisor.subscribe();
+    let mut results = match action {
+        Action::Start => supervisor.start_programs(names),
+        Action::Stop => supervisor.stop_programs(names),
+        Action::Restart => supervisor.restart_programs(names),
+        Action::Pause | Action::Resume | Action::Signal(_) | Action::Reset | Action::Scale(_) => names.iter().map(|name| {
+            (name.clone(), action.run(supervisor, name, None))
+        }).collect(),
+    };
+    for (name, index) in instances {
+        results.push((format!("{}:{}", name, index), action.run(supervisor, name, Some(*index))));
+    }
+    if !matches!(action, Action::Start | Action::Restart | Action::Scale(_)) {
+        return results;
+    }
+    let started: Vec<(String, Option<u32>)> = names
+        .iter()
+        .map(|name| (name.clone(), None))
+        .chain(instances.iter().map(|(name, index)| (name.clone(), Some(*index))))
+        .filter(|(name, only)| {
+            let target = only.map_or(name.clone(), |index| format!("{}:{}", name, index));
+            results.iter().any(|(done, result)| *done == target && result.is_ok())
+        })
+        .collect();
+    for (target, result) in supervisor.await_running(&events, &started, |_| {}) {
+        if let (Err(e), Some(slot)) = (result, results.iter_mut().find(|(done, _)| *done == target)) {
+            slot.1 = Err(e);
+        }
+    }
+    results
+}
+
+/// Describes what an event did to an instance, for the transitions printed
+/// while waiting and for notifications.
+pub fn transition(event: &Event) -> Option<(&str, u32, String)> {
+    match event {
+        Event::Spawned { program, instance, pid } => Some((program, *instance, format!("spawned (pid {})", pid))),
+        Event::SpawnFailed { program, instance, error } => Some((program, *instance, format!("failed to spawn: {}", error))),
+        Event::Running { program, instance, .. } => Some((program, *instance, "RUNNING".to_string())),
+        Event::Exited { program, instance, status, .. } => Some((program, *instance, format!("exited with status {}", status))),
+        Event::Backoff { program, instance, delay_ms } => Some((program, *instance, format!("BACKOFF (retry in {:.1}s)", *delay_ms as f64 / 1000.0))),
+        Event::Stopping { program, instance, .. } => Some((program, *instance, "STOPPING".to_string())),
+        Event::Stopped { program, instance, .. } => Some((program, *instance, "STOPPED".to_string())),
+        Event::Killed { program, instance, .. } => Some((program, *instance, "killed with SIGKILL".to_string())),
+        Event::Fatal { program, instance } => Some((program, *instance, "FATAL".to_string())),
+        Event::Paused { program, instance, .. } => Some((program, *instance, "PAUSED".to_string())),
+        Event::Resumed { program, instance, .. } => Some((program, *instance, "resumed".to_string())),
+        Event::DependencyFatal { .. } | Event::Reloaded | Event::ReloadFailed { .. } => None,
+    }
+}
+
+/// Prints one line per target of a batch command. Returns whether every
+/// target succeeded.
+fn report_results(action: Action, results: JobOutcome, json: bool) -> bool {
+    let command = action.command();
+    let mut ok = true;
+    for (name, result) in results {
+        ok &= result.is_ok();
+        match result {
+            Ok(()) if json => println!("{}", json!({ "command": command, "program": name, "ok": true })),
+            Ok(()) => println!("{}: {}", name, action.done()),
+            Err(e) if json => println!("{}", json!({
+                "command": command,
+                "program": name,
+                "ok": false,
+                "error": { "kind": e.kind, "message": e.message },
+            })),
+            Err(e) => println!("{}: {}", name, e),
+        }
+    }
+    ok
+}
+
+/// Lists every job with whether it is still running.
+fn list_jobs(supervisor: &Supervisor, json: bool) -> bool {
+    let jobs = supervisor.jobs();
+    if json {
+        let jobs: Vec<_> = jobs.iter().map(|job| json!({ "id": job.id(), "command": job.description(), "state": job_state(job) })).collect();
+        println!("{}", json!({ "command": "jobs", "ok": true, "jobs": jobs }));
+        return true;
+    }
+    for job in jobs {
+        println!("{:>4} {:<8} {}", job.id(), job_state(&job), job.description());
+    }
+    true
+}
+
+fn job_state(job: &JobHandle) -> &'static str {
+    if !job.is_done() {
+        "running"
+    } else if job.succeeded() {
+        "done"
+    } else {
+        "failed"
+    }
+}
+
+/// `wait [job]...` blocks until the given jobs, or every running one, have
+/// finished, and reports their results.
+fn wait_jobs(supervisor: &Supervisor, ids: &[&str], json: bool) -> bool {
+    let mut jobs = Vec::new();
+    for id in ids {
+        match id.parse::<u32>().ok().and_then(|id| supervisor.job(id)) {
+            Some(job) => jobs.push(job),
+            None => return report_error(json, "wait", "not_found", &format!("Job {} not found", id)),
+        }
+    }
+    if ids.is_empty() {
+        jobs = supervisor.jobs().into_iter().filter(|job| !job.is_done()).collect();
+    }
+    let mut ok = true;
+    for job in jobs {
+        let results = job.wait(None).unwrap_or_default();
+        ok &= job.succeeded();
+        if json {
+            let results: Vec<_> = results.iter().map(|(name, result)| match result {
+                Ok(()) => json!({ "program": name, "ok": true }),
+                Err(e) => json!({ "program": name, "ok": false, "error": { "kind": e.kind, "message": e.message } }),
+            }).collect();
+            println!("{}", json!({ "command": "wait", "job": job.id(), "description": job.description(), "ok": job.succeeded(), "results": results }));
+            continue;
+        }
+        println!("[job {}] {}: {}", job.id(), job.description(), job_state(&job));
+        for (name, result) in results {
+            if let Err(e) = result {
+                println!("{}: {}", name, e);
+            }
+        }
+    }
+    ok
+}
+
+/// `notify` shows the notification level, `notify <level>` sets it.
+fn notify(notifier: Option<&Notifier>, level: Option<&str>) -> bool {
+    let Some(notifier) = notifier else {
+        println!("Notifications are only shown in the interactive shell");
+        return false;
+    };
+    match level.map(|level| (level, Notifications::parse(level))) {
+        None => println!("notifications: {}", notifier.level().as_str()),
+        Some((_, Some(level))) => {
+            notifier.set_level(level);
+            println!("notifications: {}", level.as_str());
+        }
+        Some((level, None)) => {
+            println!("Unknown level {}, expected quiet, normal or verbose", level);
+            return false;
+        }
+    }
+    true
+}
+
+/// Prints a failure that is not tied to a program. Always returns false,
+/// so that callers can return it as their outcome.
+fn report_error(json: bool, command: &str, kind: &str, message: &str) -> bool {
+    if json {
+        println!("{}", json!({ "command": command, "ok": false, "error": { "kind": kind, "message": message } }));
+    } else {
+        println!("{}", message);
+    }
+    false
+}
+
+/// Splits an instance target such as `api:2` into the program and the
+/// index. Other targets, group ones included, come back without an index.
+pub fn split_instance<'a>(supervisor: &Supervisor, target: &'a str) -> Result<(&'a str, Option<u32>), (&'static str, String)> {
+    match target.split_once(':') {
+        Some((prefix, _)) if supervisor.groups().iter().any(|(group, _)| group == prefix) => Ok((target, None)),
+        Some((name, index)) => match index.parse::<u32>() {
+            Ok(index) => Ok((name, Some(index))),
+            Err(_) => Err(("invalid_instance", format!("Invalid instance {:?}", index))),
+        },
+        None => Ok((target, None)),
+    }
+}
+
+/// The status of `target`, any program target or `program:index`, or of
+/// every program without one. Only the matching instances are kept.
+fn select_status(supervisor: &Supervisor, target: Option<&str>) -> Result<Vec<ProgramStatus>, (&'static str, String)> {
+    let Some(target) = target else {
+        return Ok(supervisor.status());
+    };
+    let (pattern, index) = split_instance(supervisor, target)?;
+    let names = supervisor.resolve(pattern).map_err(|e| (e.kind(), e.to_string()))?;
+    let mut programs: Vec<ProgramStatus> = supervisor.status().into_iter().filter(|program| names.contains(&program.name)).collect();
+    if let Some(index) = index {
+        for program in &mut programs {
+            program.instances.retain(|instance| instance.instance == index);
+        }
+        if programs.iter().all(|program| program.instances.is_empty()) {
+            return Err(("not_found", format!("Instance {} not found", target)));
+        }
+    }
+    Ok(programs)
+}
+
+/// Prints one row per instance, nested under their groups with `grouped`,
+/// or the whole status as a JSON document.
+fn print_status(supervisor: &Supervisor, target: Option<&str>, json: bool, grouped: bool) -> bool {
+    let programs = match select_status(supervisor, target) {
+        Ok(programs) => programs,
+        Err((kind, message)) => return report_error(json, "status", kind, &message),
+    };
+    let groups = supervisor.groups();
+    if json {
+        let programs: Vec<_> = programs.iter().map(ProgramStatus::to_json).collect();
+        let groups: Vec<_> = groups.iter().map(|(name, members)| json!({ "name": name, "programs": members })).collect();
+        println!("{}", json!({ "command": "status", "ok": true, "programs": programs, "groups": groups }));
+        return true;
+    }
+    if programs.is_empty() {
+        return true;
+    }
+    if !grouped {
+        println!("{}", status_header(""));
+        for program in &programs {
+            for instance in &program.instances {
+                println!("{}", status_row(&program.name, instance));
+            }
+        }
+        print_overrides(&programs);
+        return true;
+    }
+    let ungrouped: Vec<String> = programs
+        .iter()
+        .map(|program| program.name.clone())
+        .filter(|name| !groups.iter().any(|(_, members)| members.contains(name)))
+        .collect();
+    println!("{}", status_header("  "));
+    for (group, members) in groups.iter().map(|(group, members)| (group.as_str(), members)).chain([("ungrouped", &ungrouped)]) {
+        let members: Vec<&ProgramStatus> = programs.iter().filter(|program| members.contains(&program.name)).collect();
+        if members.is_empty() {
+            continue;
+        }
+        println!("[{}]", group);
+        for program in members {
+            for instance in &program.instances {
+                println!("  {}", status_row(&program.name, instance));
+            }
+        }
+    }
+    print_overrides(&programs);
+    true
+}
+
+fn print_overrides(programs: &[ProgramStatus]) {
+    for program in programs {
+        if let Some(numprocs) = program.numprocs_override {
+            println!("{}: numprocs {} is a runtime override (config says {})", program.name, numprocs, program.program.numprocs);
+        }
+    }
+}
+
+fn status_header(indent: &str) -> String {
+    format!("{}{:<20} {:<9} {:>7} {:>10} {:<24} {:<10} {:>8} STARTED", indent, "NAME", "STATE", "PID", "UPTIME", "START TIME", "LAST EXIT", "RESTARTS")
+}
+
+fn status_row(name: &str, instance: &InstanceStatus) -> String {
+    format!(
+        "{:<20} {:<9} {:>7} {:>10} {:<24} {:<10} {:>8} {}",
+        format!("{}:{}", name, instance.instance),
+        instance.state,
+        instance.pid.map_or("-".to_string(), |pid| pid.to_string()),
+        match (instance.uptime, instance.next_retry) {
+            (Some(uptime), _) => format_uptime(uptime),
+            (None, Some(next_retry)) => format!("in {}", format_uptime(next_retry.duration_since(SystemTime::now()).unwrap_or_default())),
+            (None, None) => "-".to_string(),
+        },
+        instance.started_at.map_or("-".to_string(), rfc3339),
+        instance.last_exit.map_or("-".to_string(), |exit| exit.to_string()),
+        instance.restarts,
+        if instance.successfully_started { "yes" } else { "no" }
+    )
+}
+
+fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
 }
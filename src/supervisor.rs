@@ -0,0 +1,1510 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{self, Child};
+use std::sync::mpsc::Receiver;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use serde_json::{json, Value};
+use crate::commands::spawn_process;
+use crate::config::{Config, ConfigError, Program};
+use crate::events::{Event, EventBus};
+use crate::jobs::{JobHandle, JobResults, Jobs};
+use crate::logger::{rfc3339, Level, Logger};
+use crate::signals;
+use crate::targets::{glob_match, is_glob};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Stopped,
+    Starting,
+    Running,
+    Stopping,
+    Paused,
+    Exited,
+    /// Waiting for `next_retry` before the next attempt to start.
+    Backoff,
+    Fatal,
+}
+
+impl ProcessState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProcessState::Stopped => "STOPPED",
+            ProcessState::Starting => "STARTING",
+            ProcessState::Running => "RUNNING",
+            ProcessState::Stopping => "STOPPING",
+            ProcessState::Paused => "PAUSED",
+            ProcessState::Exited => "EXITED",
+            ProcessState::Backoff => "BACKOFF",
+            ProcessState::Fatal => "FATAL",
+        }
+    }
+
+    /// Whether the instance has a live process.
+    pub fn is_alive(&self) -> bool {
+        matches!(self, ProcessState::Starting | ProcessState::Running | ProcessState::Stopping | ProcessState::Paused)
+    }
+}
+
+impl fmt::Display for ProcessState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// How a process ended: with an exit code or killed by a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Code(i32),
+    Signal(i32),
+}
+
+impl ExitStatus {
+    /// The exit code compared against `exitcodes`, -1 for a signal.
+    pub fn code(&self) -> i32 {
+        match self {
+            ExitStatus::Code(code) => *code,
+            ExitStatus::Signal(_) => -1,
+        }
+    }
+}
+
+impl From<process::ExitStatus> for ExitStatus {
+    fn from(status: process::ExitStatus) -> Self {
+        match status.code() {
+            Some(code) => ExitStatus::Code(code),
+            None => ExitStatus::Signal(status.signal().unwrap_or(0)),
+        }
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExitStatus::Code(code) => write!(f, "exit {}", code),
+            ExitStatus::Signal(signal) => write!(f, "signal {}", signal),
+        }
+    }
+}
+
+/// One instance slot of a program. Slots outlive their processes so that
+/// the last exit and the restart count stay visible once they are gone.
+pub struct ProcessInfo {
+    pub instance: u32,
+    pub state: ProcessState,
+    pub child: Option<Child>,
+    pub start_time: Instant,
+    pub started_at: Option<SystemTime>,
+    pub successfully_started: bool,
+    pub last_exit: Option<ExitStatus>,
+    pub restarts: u32,
+    /// When the instance was paused, so that time spent paused does not
+    /// count towards `starttime`.
+    pub paused_at: Option<Instant>,
+    /// Failed starts since the instance was last RUNNING, which the
+    /// backoff grows with.
+    pub failures: u32,
+    /// Spawn attempts that failed in a row, bounded by `startretries`.
+    pub spawn_failures: u32,
+    /// When an instance in BACKOFF is tried again.
+    pub next_retry: Option<Instant>,
+    /// When the autorestarts within the last `restart_window` happened,
+    /// oldest first.
+    pub recent_restarts: Vec<Instant>,
+}
+
+impl ProcessInfo {
+    pub fn new(instance: u32) -> Self {
+        ProcessInfo {
+            instance,
+            state: ProcessState::Stopped,
+            child: None,
+            start_time: Instant::now(),
+            started_at: None,
+            successfully_started: false,
+            last_exit: None,
+            restarts: 0,
+            paused_at: None,
+            failures: 0,
+            spawn_failures: 0,
+            next_retry: None,
+            recent_restarts: Vec::new(),
+        }
+    }
+
+    pub fn pid(&self) -> Option<u32> {
+        self.child.as_ref().map(|child| child.id())
+    }
+
+    /// Sends `signal` to the process group the instance leads.
+    pub fn signal_group(&self, signal: i32) -> io::Result<()> {
+        let pid = self.pid().ok_or_else(|| io::Error::from_raw_os_error(libc::ESRCH))?;
+        if unsafe { libc::kill(-(pid as libc::pid_t), signal) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Records how the process ended and leaves the slot in `state`.
+    fn reap(&mut self, status: process::ExitStatus, state: ProcessState) -> ExitStatus {
+        let exit = ExitStatus::from(status);
+        self.child = None;
+        self.last_exit = Some(exit);
+        self.state = state;
+        exit
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstanceStatus {
+    pub instance: u32,
+    pub state: ProcessState,
+    pub pid: Option<u32>,
+    pub uptime: Option<Duration>,
+    pub started_at: Option<SystemTime>,
+    pub last_exit: Option<ExitStatus>,
+    pub restarts: u32,
+    pub successfully_started: bool,
+    /// When an instance in BACKOFF is tried again.
+    pub next_retry: Option<SystemTime>,
+}
+
+impl From<&ProcessInfo> for InstanceStatus {
+    fn from(process_info: &ProcessInfo) -> Self {
+        InstanceStatus {
+            instance: process_info.instance,
+            state: process_info.state,
+            pid: process_info.pid(),
+            uptime: process_info.pid().map(|_| process_info.start_time.elapsed()),
+            started_at: process_info.started_at,
+            last_exit: process_info.last_exit,
+            restarts: process_info.restarts,
+            successfully_started: process_info.successfully_started,
+            next_retry: process_info.next_retry.map(|at| SystemTime::now() + at.saturating_duration_since(Instant::now())),
+        }
+    }
+}
+
+impl InstanceStatus {
+    pub fn to_json(&self, name: &str) -> Value {
+        json!({
+            "name": format!("{}:{}", name, self.instance),
+            "instance": self.instance,
+            "state": self.state.as_str(),
+            "pid": self.pid,
+            "uptime_secs": self.uptime.map(|uptime| uptime.as_secs()),
+            "started_at": self.started_at.map(rfc3339),
+            "last_exit": self.last_exit.map(|exit| match exit {
+                ExitStatus::Code(code) => json!({ "code": code }),
+                ExitStatus::Signal(signal) => json!({ "signal": signal }),
+            }),
+            "restarts": self.restarts,
+            "successfully_started": self.successfully_started,
+            "next_retry_at": self.next_retry.map(rfc3339),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramStatus {
+    pub name: String,
+    /// The program as configured, without the runtime override.
+    pub program: Program,
+    /// The `numprocs` set with `scale`, if any.
+    pub numprocs_override: Option<u32>,
+    pub instances: Vec<InstanceStatus>,
+}
+
+impl ProgramStatus {
+    /// The status as JSON. Keys are only ever added to this schema, so
+    /// scripts can rely on the existing ones.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "config": {
+                "cmd": self.program.cmd,
+                "numprocs": self.program.numprocs,
+                "autostart": self.program.autostart,
+                "autorestart": self.program.autorestart.as_str(),
+                "exitcodes": self.program.exitcodes,
+                "startretries": self.program.startretries,
+                "starttime": self.program.starttime,
+                "stopsignal": self.program.stopsignal,
+                "stoptime": self.program.stoptime,
+                "priority": self.program.priority,
+                "depends_on": self.program.depends_on,
+                "backoff_initial": self.program.backoff_initial,
+                "backoff_max": self.program.backoff_max,
+                "backoff_multiplier": self.program.backoff_multiplier,
+                "backoff_jitter": self.program.backoff_jitter,
+                "max_restarts": self.program.max_restarts,
+                "restart_window": self.program.restart_window,
+                "workingdir": self.program.workingdir,
+            },
+            "numprocs_override": self.numprocs_override,
+            "instances": self.instances.iter().map(|instance| instance.to_json(&self.name)).collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum SupervisorError {
+    NotFound(String),
+    InstanceNotFound { program: String, instance: u32 },
+    AlreadyRunning(String),
+    NotRunning(String),
+    NotPaused(String),
+    NotFatal(String),
+    StartFailed { program: String, failed: u32 },
+    DependencyNotRunning { program: String, dependency: String },
+    DependencyFailed { program: String, dependency: String },
+    ShuttingDown,
+    Config(ConfigError),
+}
+
+impl fmt::Display for SupervisorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SupervisorError::NotFound(name) => write!(f, "Program {} not found", name),
+            SupervisorError::InstanceNotFound { program, instance } => write!(f, "Instance {}:{} not found", program, instance),
+            SupervisorError::AlreadyRunning(name) => write!(f, "Program {} is already running", name),
+            SupervisorError::NotRunning(name) => write!(f, "Program {} is not running", name),
+            SupervisorError::NotPaused(name) => write!(f, "Program {} is not paused", name),
+            SupervisorError::NotFatal(name) => write!(f, "Program {} is not FATAL", name),
+            SupervisorError::StartFailed { program, failed } => write!(f, "{} instance(s) of {} failed to start", failed, program),
+            SupervisorError::DependencyNotRunning { program, dependency } => write!(f, "Program {} depends on {}, which is not running", program, dependency),
+            SupervisorError::DependencyFailed { program, dependency } => write!(f, "Program {} was not started because {} failed to start", program, dependency),
+            SupervisorError::ShuttingDown => write!(f, "Taskmaster is shutting down"),
+            SupervisorError::Config(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl SupervisorError {
+    /// A stable identifier for the error, used in JSON output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SupervisorError::NotFound(_) => "not_found",
+            SupervisorError::InstanceNotFound { .. } => "instance_not_found",
+            SupervisorError::AlreadyRunning(_) => "already_running",
+            SupervisorError::NotRunning(_) => "not_running",
+            SupervisorError::NotPaused(_) => "not_paused",
+            SupervisorError::NotFatal(_) => "not_fatal",
+            SupervisorError::StartFailed { .. } => "start_failed",
+            SupervisorError::DependencyNotRunning { .. } => "dependency_not_running",
+            SupervisorError::DependencyFailed { .. } => "dependency_failed",
+            SupervisorError::ShuttingDown => "shutting_down",
+            SupervisorError::Config(_) => "config",
+        }
+    }
+}
+
+impl std::error::Error for SupervisorError {}
+
+/// What `Supervisor::shutdown` had to do.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownSummary {
+    /// Instances that exited after their stopsignal.
+    pub stopped: u32,
+    /// Instances that were SIGKILLed after their stoptime.
+    pub killed: u32,
+}
+
+impl ShutdownSummary {
+    /// Whether every instance exited on its stopsignal.
+    pub fn is_clean(&self) -> bool {
+        self.killed == 0
+    }
+}
+
+/// A program, or only one of its instances.
+type Target = (String, Option<u32>);
+
+/// Names a program, or one of its instances as `program:index`.
+fn instance_target(name: &str, only: Option<u32>) -> String {
+    match only {
+        Some(instance) => format!("{}:{}", name, instance),
+        None => name.to_string(),
+    }
+}
+
+/// Owns the configured programs and their instances. Every path that
+/// starts or stops processes (autostart, reload, shell commands and the
+/// monitor's autorestart) goes through these methods.
+#[derive(Clone)]
+pub struct Supervisor {
+    pub(crate) programs: Arc<Mutex<HashMap<String, Program>>>,
+    groups: Arc<Mutex<HashMap<String, Vec<String>>>>,
+    /// `numprocs` set with `scale`, kept over the config until reset.
+    overrides: Arc<Mutex<HashMap<String, u32>>>,
+    pub(crate) processes: Arc<Mutex<HashMap<String, Vec<ProcessInfo>>>>,
+    pub(crate) logger: Arc<Logger>,
+    config_path: Option<PathBuf>,
+    events: Arc<EventBus>,
+    jobs: Arc<Jobs>,
+    /// Set by `shutdown`, after which nothing is started any more.
+    shutting_down: Arc<AtomicBool>,
+}
+
+impl Supervisor {
+    pub fn new(config: Config, logger: Arc<Logger>) -> Self {
+        Supervisor {
+            programs: Arc::new(Mutex::new(config.programs)),
+            groups: Arc::new(Mutex::new(config.groups)),
+            overrides: Arc::new(Mutex::new(HashMap::new())),
+            processes: Arc::new(Mutex::new(HashMap::new())),
+            logger,
+            config_path: config.path,
+            events: Arc::new(EventBus::default()),
+            jobs: Arc::new(Jobs::default()),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn logger(&self) -> &Arc<Logger> {
+        &self.logger
+    }
+
+    /// Returns a receiver for every event emitted from now on.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    fn emit(&self, event: Event) {
+        self.events.emit(event);
+    }
+
+    /// Runs `task` on a thread of its own and returns a handle to follow
+    /// it. The job stays listed by `jobs` for a while once finished.
+    pub fn spawn_job<F>(&self, description: &str, task: F) -> JobHandle
+    where
+        F: FnOnce(&Supervisor) -> JobResults + Send + 'static,
+    {
+        let job = self.jobs.create(description.to_string());
+        let supervisor = self.clone();
+        let handle = job.clone();
+        thread::spawn(move || handle.finish(task(&supervisor)));
+        job
+    }
+
+    /// Every job started so far, oldest first.
+    pub fn jobs(&self) -> Vec<JobHandle> {
+        self.jobs.list()
+    }
+
+    pub fn job(&self, id: u32) -> Option<JobHandle> {
+        self.jobs.get(id)
+    }
+
+    /// Waits until every STARTING or BACKOFF instance of `targets`, a
+    /// program or one of its instances each, is RUNNING or has failed. An
+    /// instance fails when it exits, is stopped or becomes FATAL before
+    /// `starttime`; an instance paused meanwhile counts as started. `events`
+    /// must have been subscribed before the instances were started, and the
+    /// monitor must be polling. Every event of the targets is passed to `progress` as it
+    /// arrives. Returns one result per target.
+    pub fn await_running<F: FnMut(&Event)>(&self, events: &Receiver<Event>, targets: &[(String, Option<u32>)], progress: F) -> JobResults {
+        let mut failures: HashMap<(String, Option<u32>), u32> = HashMap::new();
+        self.await_each(events, targets, progress, |target, failed| {
+            failures.insert(target, failed);
+            Vec::new()
+        });
+        targets.iter().map(|(name, only)| {
+            let result = match failures.get(&(name.clone(), *only)) {
+                Some(&failed) if failed > 0 => Err(SupervisorError::StartFailed { program: instance_target(name, *only), failed }),
+                _ => Ok(()),
+            };
+            (instance_target(name, *only), result)
+        }).collect()
+    }
+
+    /// Does the waiting of `await_running`, handing each target to
+    /// `settled` with its number of failed instances as soon as it is
+    /// settled, rather than once they all are. `settled` returns more
+    /// targets to await, if any.
+    fn await_each<F, S>(&self, events: &Receiver<Event>, targets: &[Target], mut progress: F, mut settled: S)
+    where
+        F: FnMut(&Event),
+        S: FnMut(Target, u32) -> Vec<Target>,
+    {
+        let mut awaited: Vec<Target> = Vec::new();
+        let mut pending: Vec<(Target, u32, Option<u32>)> = Vec::new();
+        let mut failed: HashMap<Target, u32> = HashMap::new();
+        let mut added = targets.to_vec();
+        loop {
+            while !added.is_empty() {
+                let targets = std::mem::take(&mut added);
+                {
+                    let processes = self.processes.lock().unwrap();
+                    for (name, only) in &targets {
+                        let instances = processes.get(name).map(Vec::as_slice).unwrap_or_default();
+                        for process_info in instances.iter().filter(|process_info| only.is_none_or(|only| only == process_info.instance)) {
+                            let target = (name.clone(), *only);
+                            match process_info.state {
+                                ProcessState::Starting | ProcessState::Backoff => pending.push((target, process_info.instance, process_info.pid())),
+                                ProcessState::Exited | ProcessState::Fatal => *failed.entry(target).or_default() += 1,
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+                for target in targets {
+                    if pending.iter().any(|(pending, _, _)| *pending == target) {
+                        awaited.push(target);
+                    } else {
+                        let failed = failed.get(&target).copied().unwrap_or_default();
+                        added.extend(settled(target, failed));
+                    }
+                }
+            }
+            if pending.is_empty() {
+                break;
+            }
+            let Ok(event) = events.recv() else {
+                break;
+            };
+            let (program, instance, pid, started) = match &event {
+                Event::Running { program, instance, pid } | Event::Paused { program, instance, pid } => (program, *instance, Some(*pid), Some(true)),
+                Event::Exited { program, instance, pid, .. }
+                | Event::Stopped { program, instance, pid }
+                | Event::Killed { program, instance, pid } => (program, *instance, Some(*pid), Some(false)),
+                Event::Fatal { program, instance } => (program, *instance, None, Some(false)),
+                Event::Spawned { program, instance, .. }
+                | Event::SpawnFailed { program, instance, .. }
+                | Event::Stopping { program, instance, .. }
+                | Event::Resumed { program, instance, .. }
+                | Event::Backoff { program, instance, .. } => (program, *instance, None, None),
+                Event::DependencyFatal { .. } | Event::Reloaded | Event::ReloadFailed { .. } => continue,
+            };
+            if !awaited.iter().any(|(name, only)| name == program && only.is_none_or(|only| only == instance)) {
+                continue;
+            }
+            progress(&event);
+            let Some(started) = started else {
+                continue;
+            };
+            // Events of a process that was replaced, such as the one a
+            // restart stopped, do not settle the new one.
+            let Some(position) = pending
+                .iter()
+                .position(|((name, _), pending, pending_pid)| {
+                    name == program && *pending == instance && pid.is_none_or(|pid| pending_pid.is_none_or(|pending_pid| pid == pending_pid))
+                })
+            else {
+                continue;
+            };
+            let (target, _, _) = pending.remove(position);
+            if !started {
+                *failed.entry(target.clone()).or_default() += 1;
+            }
+            if !pending.iter().any(|(pending, _, _)| *pending == target) {
+                awaited.retain(|awaited| *awaited != target);
+                let failed = failed.get(&target).copied().unwrap_or_default();
+                added.extend(settled(target, failed));
+            }
+        }
+    }
+
+    /// The program as configured, with any `numprocs` override applied.
+    fn program(&self, name: &str) -> Result<Program, SupervisorError> {
+        let mut program = self.programs
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| SupervisorError::NotFound(name.to_string()))?;
+        if let Some(&numprocs) = self.overrides.lock().unwrap().get(name) {
+            program.numprocs = numprocs;
+        }
+        Ok(program)
+    }
+
+    pub fn program_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.programs.lock().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Returns every group, sorted by name, with its members.
+    pub fn groups(&self) -> Vec<(String, Vec<String>)> {
+        let mut groups: Vec<(String, Vec<String>)> = self.groups
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, members)| (name.clone(), members.clone()))
+            .collect();
+        groups.sort();
+        groups
+    }
+
+    /// Expands a shell target into program names: `all`, a glob such as
+    /// `web-*`, a single program name, or the members of a group as
+    /// `group:*` or `group:member`.
+    pub fn resolve(&self, target: &str) -> Result<Vec<String>, SupervisorError> {
+        let names = self.program_names();
+        let group = target
+            .split_once(':')
+            .and_then(|(group, member)| Some((self.groups.lock().unwrap().get(group)?.clone(), member)));
+        let matched: Vec<String> = if let Some((members, member)) = group {
+            names.into_iter().filter(|name| members.contains(name) && glob_match(member, name)).collect()
+        } else if target == "all" {
+            names
+        } else if is_glob(target) {
+            names.into_iter().filter(|name| glob_match(target, name)).collect()
+        } else {
+            names.into_iter().filter(|name| name == target).collect()
+        };
+        if matched.is_empty() {
+            return Err(SupervisorError::NotFound(target.to_string()));
+        }
+        Ok(matched)
+    }
+
+    /// Orders `names` by priority, then by name.
+    pub fn sort_by_priority(&self, names: &mut [String]) {
+        let programs = self.programs.lock().unwrap();
+        let priority = |name: &String| programs.get(name).map_or(i32::MAX, |program| program.priority);
+        names.sort_by(|a, b| priority(a).cmp(&priority(b)).then_with(|| a.cmp(b)));
+    }
+
+    /// Splits `names` into waves: a program comes in the wave after the
+    /// last of its dependencies among `names`. Each wave is in priority
+    /// order.
+    pub fn start_order(&self, names: &[String]) -> Vec<Vec<String>> {
+        let programs = self.programs.lock().unwrap();
+        let mut waves: HashMap<&String, usize> = HashMap::new();
+        // Config validation rules out cycles, so rounds go on placing
+        // programs until all are placed; duplicate names are placed once.
+        let mut placed = true;
+        while placed {
+            placed = false;
+            for name in names {
+                let dependencies: Vec<&String> = programs
+                    .get(name)
+                    .map(|program| program.depends_on.iter().filter(|dependency| names.contains(dependency)).collect())
+                    .unwrap_or_default();
+                if waves.contains_key(name) || dependencies.iter().any(|dependency| !waves.contains_key(dependency)) {
+                    continue;
+                }
+                let wave = dependencies.iter().map(|dependency| waves[dependency] + 1).max().unwrap_or(0);
+                waves.insert(name, wave);
+                placed = true;
+            }
+        }
+        let mut order = vec![Vec::new(); waves.values().max().map_or(0, |last| last + 1)];
+        for (name, wave) in waves {
+            order[wave].push(name.clone());
+        }
+        drop(programs);
+        for wave in &mut order {
+            self.sort_by_priority(wave);
+        }
+        order
+    }
+
+    /// Starts `names` in priority order, going on past failures. Each
+    /// program is started as soon as its dependencies among `names` are
+    /// RUNNING, whatever the others are doing, and is not started if one of
+    /// them failed. The monitor must be polling when `names` have
+    /// dependencies among themselves.
+    pub fn start_programs(&self, names: &[String]) -> Vec<(String, Result<(), SupervisorError>)> {
+        let mut waiting: Vec<String> = self.start_order(names).into_iter().flatten().collect();
+        self.sort_by_priority(&mut waiting);
+        let depends_on: HashMap<String, Vec<String>> = {
+            let programs = self.programs.lock().unwrap();
+            waiting.iter().map(|name| (name.clone(), programs.get(name).map(|program| program.depends_on.clone()).unwrap_or_default())).collect()
+        };
+        let events = self.subscribe();
+        let mut results = Vec::new();
+        let mut pending = Vec::new();
+        let awaited = self.start_ready(&depends_on, &mut waiting, &mut pending, &mut results);
+        self.await_each(&events, &awaited, |_| {}, |(name, only), failed| {
+            if let (true, Some(slot)) = (failed > 0, results.iter_mut().find(|(done, _)| *done == name)) {
+                slot.1 = Err(SupervisorError::StartFailed { program: instance_target(&name, only), failed });
+            }
+            pending.retain(|pending| *pending != name);
+            self.start_ready(&depends_on, &mut waiting, &mut pending, &mut results)
+        });
+        // Only left over if the events stopped coming.
+        for name in waiting {
+            let result = self.start_after_dependencies(&name, &results);
+            results.push((name, result));
+        }
+        results
+    }
+
+    /// Starts the programs of `waiting` that wait neither on another one
+    /// nor on a `pending` one, and returns those the others depend on, to be
+    /// awaited and then moved out of `pending`.
+    fn start_ready(&self, depends_on: &HashMap<String, Vec<String>>, waiting: &mut Vec<String>, pending: &mut Vec<String>, results: &mut JobResults) -> Vec<Target> {
+        let mut awaited = Vec::new();
+        let mut index = 0;
+        while index < waiting.len() {
+            if depends_on[&waiting[index]].iter().any(|dependency| waiting.contains(dependency) || pending.contains(dependency)) {
+                index += 1;
+                continue;
+            }
+            let name = waiting.remove(index);
+            let result = self.start_after_dependencies(&name, results);
+            // One that was already up may still be STARTING, so its
+            // dependents wait for it all the same.
+            let started = matches!(result, Ok(()) | Err(SupervisorError::AlreadyRunning(_)));
+            if started && depends_on.values().any(|dependencies| dependencies.contains(&name)) {
+                pending.push(name.clone());
+                awaited.push((name.clone(), None));
+            }
+            results.push((name, result));
+        }
+        awaited
+    }
+
+    /// Starts `name` unless one of its dependencies failed to start in
+    /// `results`. Other errors, such as a dependency that was already
+    /// running, are left to `check_dependencies`.
+    fn start_after_dependencies(&self, name: &str, results: &[(String, Result<(), SupervisorError>)]) -> Result<(), SupervisorError> {
+        let program = self.program(name)?;
+        let failed = program.depends_on.into_iter().find(|dependency| {
+            results.iter().any(|(done, result)| {
+                done == dependency && matches!(result, Err(SupervisorError::StartFailed { .. } | SupervisorError::DependencyFailed { .. }))
+            })
+        });
+        let Some(dependency) = failed else {
+            return self.start_program(name);
+        };
+        self.logger.event(Level::Warn, "supervisor", "dependency_failed", &[("program", &name), ("dependency", &dependency)])
+            .expect("Failed to log message");
+        Err(SupervisorError::DependencyFailed { program: name.to_string(), dependency })
+    }
+
+    /// Stops `names` in the reverse of their start order, going on past
+    /// failures, so that dependents stop before their dependencies.
+    pub fn stop_programs(&self, names: &[String]) -> Vec<(String, Result<(), SupervisorError>)> {
+        let names: Vec<String> = self.start_order(names).into_iter().flatten().collect();
+        names.into_iter().rev().map(|name| {
+            let result = self.stop_program(&name);
+            (name, result)
+        }).collect()
+    }
+
+    /// Stops every running program of `names` in reverse start order, then
+    /// starts them all again as `start_programs` does.
+    pub fn restart_programs(&self, names: &[String]) -> Vec<(String, Result<(), SupervisorError>)> {
+        let mut results = Vec::new();
+        let mut restarted = Vec::new();
+        for (name, result) in self.stop_programs(names) {
+            match result {
+                Err(e) if !matches!(e, SupervisorError::NotRunning(_)) => results.push((name, Err(e))),
+                _ => restarted.push(name),
+            }
+        }
+        results.extend(self.start_programs(&restarted));
+        results
+    }
+
+    /// Returns every configured program, sorted by name, with one entry per
+    /// instance, including instances that were never started.
+    pub fn status(&self) -> Vec<ProgramStatus> {
+        let mut programs: Vec<(String, Program)> = self.programs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, program)| (name.clone(), program.clone()))
+            .collect();
+        programs.sort_by(|a, b| a.0.cmp(&b.0));
+        let overrides = self.overrides.lock().unwrap().clone();
+        let processes = self.processes.lock().unwrap();
+        programs
+            .into_iter()
+            .map(|(name, program)| {
+                let numprocs_override = overrides.get(&name).copied();
+                let slots = processes.get(&name).map(Vec::as_slice).unwrap_or_default();
+                let count = slots.len().max(numprocs_override.unwrap_or(program.numprocs) as usize) as u32;
+                let instances = (0..count)
+                    .map(|i| match slots.get(i as usize) {
+                        Some(process_info) => InstanceStatus::from(process_info),
+                        None => InstanceStatus::from(&ProcessInfo::new(i)),
+                    })
+                    .collect();
+                ProgramStatus { name, program, numprocs_override, instances }
+            })
+            .collect()
+    }
+
+    /// Starts every autostart program that is not running as
+    /// `start_programs` does, in a job that lasts until they are all
+    /// RUNNING or have failed and then logs how long that took. Returns
+    /// the job, if there was anything to start.
+    pub fn autostart_programs(&self) -> Option<JobHandle> {
+        if self.is_shutting_down() {
+            return None;
+        }
+        let names: Vec<String> = {
+            let processes = self.processes.lock().unwrap();
+            let alive = |name: &String| {
+                processes.get(name).is_some_and(|instances| instances.iter().any(|process_info| process_info.state.is_alive()))
+            };
+            self.programs
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(name, program)| program.autostart && !alive(name))
+                .map(|(name, _)| name.clone())
+                .collect()
+        };
+        if names.is_empty() {
+            return None;
+        }
+        Some(self.spawn_job("autostart", move |supervisor| {
+            let started_at = Instant::now();
+            let events = supervisor.subscribe();
+            // Failures are logged and published as FATAL events.
+            let mut results = supervisor.start_programs(&names);
+            let started: Vec<(String, Option<u32>)> = results
+                .iter()
+                .filter(|(_, result)| result.is_ok())
+                .map(|(name, _)| (name.clone(), None))
+                .collect();
+            for (target, result) in supervisor.await_running(&events, &started, |_| {}) {
+                if let (Err(e), Some(slot)) = (result, results.iter_mut().find(|(name, _)| *name == target)) {
+                    slot.1 = Err(e);
+                }
+            }
+            let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+            let level = if failed == 0 { Level::Info } else { Level::Warn };
+            let elapsed_ms = started_at.elapsed().as_millis() as u64;
+            supervisor.logger.event(level, "supervisor", "startup", &[("programs", &results.len()), ("failed", &failed), ("elapsed_ms", &elapsed_ms)])
+                .expect("Failed to log message");
+            results
+        }))
+    }
+
+    /// Starts `numprocs` instances of a program that is not running. Each
+    /// instance gets up to `startretries` attempts; instances that could be
+    /// spawned are kept even if others failed. Every dependency must have a
+    /// RUNNING instance first.
+    pub fn start_program(&self, name: &str) -> Result<(), SupervisorError> {
+        if self.is_shutting_down() {
+            return Err(SupervisorError::ShuttingDown);
+        }
+        let program = self.program(name)?;
+        self.check_dependencies(name, &program)?;
+        let mut processes = self.processes.lock().unwrap();
+        let instances = processes.entry(name.to_string()).or_default();
+        if instances.iter().any(|process_info| process_info.state.is_alive()) {
+            return Err(SupervisorError::AlreadyRunning(name.to_string()));
+        }
+        instances.truncate(program.numprocs as usize);
+        while instances.len() < program.numprocs as usize {
+            instances.push(ProcessInfo::new(instances.len() as u32));
+        }
+        drop(processes);
+        // The lock is taken per instance so that programs started side by
+        // side interleave their spawns.
+        let mut failed = 0;
+        for instance in 0..program.numprocs as usize {
+            let mut processes = self.processes.lock().unwrap();
+            let Some(process_info) = processes.get_mut(name).and_then(|instances| instances.get_mut(instance)) else {
+                break;
+            };
+            if process_info.state.is_alive() {
+                continue;
+            }
+            process_info.failures = 0;
+            process_info.spawn_failures = 0;
+            process_info.recent_restarts.clear();
+            if self.start_instance(name, &program, process_info).is_err() {
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            return Err(SupervisorError::StartFailed { program: name.to_string(), failed });
+        }
+        Ok(())
+    }
+
+    /// Starts a single instance of a program, leaving the others as they are.
+    pub fn start_program_instance(&self, name: &str, instance: u32) -> Result<(), SupervisorError> {
+        if self.is_shutting_down() {
+            return Err(SupervisorError::ShuttingDown);
+        }
+        let program = self.program(name)?;
+        if instance >= program.numprocs {
+            return Err(SupervisorError::InstanceNotFound { program: name.to_string(), instance });
+        }
+        self.check_dependencies(name, &program)?;
+        let mut processes = self.processes.lock().unwrap();
+        let instances = processes.entry(name.to_string()).or_default();
+        while instances.len() <= instance as usize {
+            instances.push(ProcessInfo::new(instances.len() as u32));
+        }
+        let process_info = &mut instances[instance as usize];
+        if process_info.state.is_alive() {
+            return Err(SupervisorError::AlreadyRunning(format!("{}:{}", name, instance)));
+        }
+        process_info.failures = 0;
+        process_info.spawn_failures = 0;
+        process_info.recent_restarts.clear();
+        self.start_instance(name, &program, process_info)
+            .map_err(|_| SupervisorError::StartFailed { program: name.to_string(), failed: 1 })
+    }
+
+    /// Fails unless every dependency of `program` has a RUNNING instance.
+    fn check_dependencies(&self, name: &str, program: &Program) -> Result<(), SupervisorError> {
+        let processes = self.processes.lock().unwrap();
+        let running = |dependency: &String| {
+            processes.get(dependency).is_some_and(|instances| instances.iter().any(|process_info| process_info.state == ProcessState::Running))
+        };
+        match program.depends_on.iter().find(|dependency| !running(dependency)) {
+            Some(dependency) => Err(SupervisorError::DependencyNotRunning { program: name.to_string(), dependency: dependency.clone() }),
+            None => Ok(()),
+        }
+    }
+
+    /// Reports an instance of `name` going FATAL on every program that
+    /// depends on it.
+    fn report_dependents(&self, name: &str) {
+        let mut dependents: Vec<String> = self.programs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, program)| program.depends_on.iter().any(|dependency| dependency == name))
+            .map(|(dependent, _)| dependent.clone())
+            .collect();
+        dependents.sort();
+        for dependent in dependents {
+            self.logger.event(Level::Error, "supervisor", "dependency_fatal", &[("program", &dependent), ("dependency", &name)])
+                .expect("Failed to log message");
+            self.emit(Event::DependencyFatal { program: dependent, dependency: name.to_string() });
+        }
+    }
+
+    /// Spawns the process of one instance slot. A failed spawn puts the
+    /// slot in BACKOFF for the monitor to try again, until `startretries`
+    /// attempts in a row have failed and it is marked FATAL.
+    pub(crate) fn start_instance(&self, name: &str, program: &Program, process_info: &mut ProcessInfo) -> io::Result<()> {
+        let instance = process_info.instance;
+        let attempts = program.startretries.max(1);
+        process_info.next_retry = None;
+        match spawn_process(name, instance, program, &self.logger) {
+            Ok(child) => {
+                let pid = child.id();
+                if process_info.started_at.is_some() {
+                    process_info.restarts += 1;
+                }
+                process_info.child = Some(child);
+                process_info.state = ProcessState::Starting;
+                process_info.start_time = Instant::now();
+                process_info.started_at = Some(SystemTime::now());
+                process_info.successfully_started = false;
+                process_info.spawn_failures = 0;
+                self.logger.event(Level::Info, "supervisor", "spawned", &[("program", &name), ("instance", &instance), ("pid", &pid)])
+                    .expect("Failed to log message");
+                self.emit(Event::Spawned { program: name.to_string(), instance, pid });
+                self.check_running_time(name, process_info, program.starttime.into());
+                Ok(())
+            }
+            Err(e) => {
+                self.logger.event(Level::Error, "supervisor", "spawn_failed", &[("program", &name), ("instance", &instance), ("error", &e)])
+                    .expect("Failed to log message");
+                self.emit(Event::SpawnFailed { program: name.to_string(), instance, error: e.to_string() });
+                process_info.spawn_failures += 1;
+                if process_info.spawn_failures >= attempts {
+                    process_info.state = ProcessState::Fatal;
+                    process_info.spawn_failures = 0;
+                    self.logger.event(Level::Crit, "supervisor", "fatal", &[("program", &name), ("instance", &instance), ("attempts", &attempts)])
+                        .expect("Failed to log message");
+                    self.emit(Event::Fatal { program: name.to_string(), instance });
+                    self.report_dependents(name);
+                    return Err(e);
+                }
+                let attempt = process_info.spawn_failures + 1;
+                self.logger.event(Level::Warn, "supervisor", "retry", &[("program", &name), ("instance", &instance), ("attempt", &attempt), ("startretries", &attempts)])
+                    .expect("Failed to log message");
+                self.back_off(name, program, process_info);
+                Ok(())
+            }
+        }
+    }
+
+    /// Backs the slot off before an autorestart, or gives up on it as FATAL
+    /// once it has used up `max_restarts` within `restart_window`. Returns
+    /// whether it gave up, for the caller to report the dependents once it
+    /// has released the programs.
+    fn schedule_restart(&self, name: &str, program: &Program, process_info: &mut ProcessInfo) -> bool {
+        let window = Duration::from_secs(program.restart_window.into());
+        process_info.recent_restarts.retain(|at| at.elapsed() < window);
+        let Some(max_restarts) = program.max_restarts.filter(|&max_restarts| process_info.recent_restarts.len() as u32 >= max_restarts) else {
+            process_info.recent_restarts.push(Instant::now());
+            self.back_off(name, program, process_info);
+            return false;
+        };
+        let instance = process_info.instance;
+        process_info.state = ProcessState::Fatal;
+        self.logger.event(Level::Crit, "monitor", "restart_limit", &[("program", &name), ("instance", &instance), ("max_restarts", &max_restarts), ("restart_window", &program.restart_window)])
+            .expect("Failed to log message");
+        self.emit(Event::Fatal { program: name.to_string(), instance });
+        true
+    }
+
+    /// Puts the slot in BACKOFF until the delay for its failures in a row
+    /// has passed. The monitor starts it again afterwards.
+    fn back_off(&self, name: &str, program: &Program, process_info: &mut ProcessInfo) {
+        process_info.failures += 1;
+        let delay = program.backoff(process_info.failures);
+        process_info.state = ProcessState::Backoff;
+        // The delay is capped, so this only guards the monitor against an
+        // instant it cannot represent.
+        let now = Instant::now();
+        process_info.next_retry = Some(now.checked_add(delay).unwrap_or(now));
+        let delay_ms = delay.as_millis() as u64;
+        self.logger.event(Level::Info, "supervisor", "backoff", &[("program", &name), ("instance", &process_info.instance), ("failures", &process_info.failures), ("delay_ms", &delay_ms)])
+            .expect("Failed to log message");
+        self.emit(Event::Backoff { program: name.to_string(), instance: process_info.instance, delay_ms });
+    }
+
+    fn check_running_time(&self, name: &str, process_info: &mut ProcessInfo, starttime: u64) {
+        let elapsed_time = process_info.start_time.elapsed().as_secs();
+        if process_info.state == ProcessState::Starting && elapsed_time >= starttime {
+            let pid = process_info.pid().unwrap_or_default();
+            self.logger.event(Level::Info, "supervisor", "running", &[("program", &name), ("instance", &process_info.instance), ("pid", &pid), ("starttime", &elapsed_time)])
+                .expect("Failed to log message");
+            process_info.successfully_started = true;
+            process_info.state = ProcessState::Running;
+            process_info.failures = 0;
+            self.emit(Event::Running { program: name.to_string(), instance: process_info.instance, pid });
+        }
+    }
+
+    /// Sends `stopsignal` to every live instance and waits up to `stoptime`
+    /// for them to exit, killing the ones that are still alive afterwards.
+    /// The instances are STOPPING meanwhile, and no lock is held while
+    /// waiting.
+    pub fn stop_program(&self, name: &str) -> Result<(), SupervisorError> {
+        self.stop_instances(name, None)
+    }
+
+    /// Stops a single instance of a program, leaving the others running.
+    pub fn stop_program_instance(&self, name: &str, instance: u32) -> Result<(), SupervisorError> {
+        self.stop_instances(name, Some(instance))
+    }
+
+    fn stop_instances(&self, name: &str, only: Option<u32>) -> Result<(), SupervisorError> {
+        let program = self.program(name)?;
+        if let Some(instance) = only {
+            let slots = self.processes.lock().unwrap().get(name).map_or(0, Vec::len) as u32;
+            if instance >= slots.max(program.numprocs) {
+                return Err(SupervisorError::InstanceNotFound { program: name.to_string(), instance });
+            }
+        }
+        if !self.stop_matching(name, &program, |instance| only.is_none_or(|only| only == instance)) {
+            return Err(SupervisorError::NotRunning(instance_target(name, only)));
+        }
+        Ok(())
+    }
+
+    /// Stops the instances of `name` that `matching` selects all together,
+    /// waiting once for them to exit. Returns whether there was anything to
+    /// stop.
+    fn stop_matching<F: Fn(u32) -> bool>(&self, name: &str, program: &Program, matching: F) -> bool {
+        let mut pending = Vec::new();
+        let mut cancelled = false;
+        {
+            let mut processes = self.processes.lock().unwrap();
+            for process_info in processes.get_mut(name).into_iter().flatten() {
+                if !matching(process_info.instance) {
+                    continue;
+                }
+                // Nothing runs during a backoff, so stopping only cancels the
+                // next attempt.
+                if process_info.state == ProcessState::Backoff {
+                    process_info.state = ProcessState::Stopped;
+                    process_info.next_retry = None;
+                    self.logger.event(Level::Info, "supervisor", "stopped", &[("program", &name), ("instance", &process_info.instance), ("pid", &0)])
+                        .expect("Failed to log message");
+                    self.emit(Event::Stopped { program: name.to_string(), instance: process_info.instance, pid: 0 });
+                    cancelled = true;
+                }
+                if matches!(process_info.state, ProcessState::Starting | ProcessState::Running | ProcessState::Paused) {
+                    let paused = process_info.state == ProcessState::Paused;
+                    let pid = process_info.pid().unwrap_or_default();
+                    process_info.state = ProcessState::Stopping;
+                    let _ = process_info.signal_group(program.stopsignal);
+                    if paused {
+                        let _ = process_info.signal_group(libc::SIGCONT);
+                    }
+                    self.logger.event(Level::Info, "supervisor", "stopping", &[("program", &name), ("instance", &process_info.instance), ("pid", &pid), ("signal", &signals::name(program.stopsignal))])
+                        .expect("Failed to log message");
+                    self.emit(Event::Stopping { program: name.to_string(), instance: process_info.instance, pid });
+                    pending.push((process_info.instance, pid));
+                }
+            }
+        }
+        if pending.is_empty() {
+            return cancelled;
+        }
+        self.await_exit(name, pending, Some(program.stoptime));
+        true
+    }
+
+    /// Waits for the `pending` instances, as (instance, pid), to exit and
+    /// reaps them into STOPPED. Instances still alive after `stoptime`
+    /// seconds are SIGKILLed; without `stoptime` they already were. The lock
+    /// is only taken to reap, so supervision goes on meanwhile.
+    fn await_exit(&self, name: &str, mut pending: Vec<(u32, u32)>, stoptime: Option<u32>) {
+        let deadline = stoptime.map(|stoptime| Instant::now() + Duration::from_secs(stoptime.into()));
+        let mut killed = deadline.is_none();
+        loop {
+            {
+                let mut processes = self.processes.lock().unwrap();
+                let instances = processes.get_mut(name).map(Vec::as_mut_slice).unwrap_or_default();
+                pending.retain(|&(instance, pid)| {
+                    let Some(process_info) = instances.get_mut(instance as usize) else {
+                        return false;
+                    };
+                    if process_info.pid() != Some(pid) {
+                        return false;
+                    }
+                    let Some(Ok(Some(status))) = process_info.child.as_mut().map(Child::try_wait) else {
+                        return true;
+                    };
+                    process_info.reap(status, ProcessState::Stopped);
+                    if killed {
+                        self.logger.event(Level::Warn, "supervisor", "killed", &[("program", &name), ("instance", &instance), ("pid", &pid)])
+                            .expect("Failed to log message");
+                        self.emit(Event::Killed { program: name.to_string(), instance, pid });
+                    } else {
+                        self.logger.event(Level::Info, "supervisor", "stopped", &[("program", &name), ("instance", &instance), ("pid", &pid)])
+                            .expect("Failed to log message");
+                        self.emit(Event::Stopped { program: name.to_string(), instance, pid });
+                    }
+                    false
+                });
+                if pending.is_empty() {
+                    return;
+                }
+                if !killed && deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    for &(instance, _) in &pending {
+                        if let Err(e) = instances[instance as usize].signal_group(libc::SIGKILL) {
+                            self.logger.event(Level::Error, "supervisor", "kill_failed", &[("program", &name), ("instance", &instance), ("error", &e)])
+                                .expect("Failed to log message");
+                        }
+                    }
+                    killed = true;
+                }
+            }
+            thread::sleep(Duration::from_millis(if killed { 10 } else { 100 }));
+        }
+    }
+
+    /// Stops the program if it is running, then starts it again.
+    pub fn restart_program(&self, name: &str) -> Result<(), SupervisorError> {
+        match self.stop_program(name) {
+            Ok(()) | Err(SupervisorError::NotRunning(_)) => self.start_program(name),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Sends `signal` to the process group of every live instance, leaving
+    /// the state alone: the monitor sees the exit if the signal causes one.
+    pub fn signal_program(&self, name: &str, signal: i32) -> Result<(), SupervisorError> {
+        self.signal_instances(name, None, signal)
+    }
+
+    /// Sends `signal` to the process group of a single instance.
+    pub fn signal_program_instance(&self, name: &str, instance: u32, signal: i32) -> Result<(), SupervisorError> {
+        self.signal_instances(name, Some(instance), signal)
+    }
+
+    fn signal_instances(&self, name: &str, only: Option<u32>, signal: i32) -> Result<(), SupervisorError> {
+        let program = self.program(name)?;
+        let processes = self.processes.lock().unwrap();
+        let instances = processes.get(name).map(Vec::as_slice).unwrap_or_default();
+        if let Some(instance) = only {
+            if instance >= (instances.len() as u32).max(program.numprocs) {
+                return Err(SupervisorError::InstanceNotFound { program: name.to_string(), instance });
+            }
+        }
+        let mut signalled = 0;
+        for process_info in instances {
+            if only.is_some_and(|instance| instance != process_info.instance) || !process_info.state.is_alive() {
+                continue;
+            }
+            let pid = process_info.pid().unwrap_or_default();
+            match process_info.signal_group(signal) {
+                Ok(()) => {
+                    signalled += 1;
+                    self.logger.event(Level::Info, "supervisor", "signalled", &[("program", &name), ("instance", &process_info.instance), ("pid", &pid), ("signal", &signals::name(signal))])
+                        .expect("Failed to log message");
+                }
+                Err(e) => {
+                    self.logger.event(Level::Error, "supervisor", "signal_failed", &[("program", &name), ("instance", &process_info.instance), ("pid", &pid), ("error", &e)])
+                        .expect("Failed to log message");
+                }
+            }
+        }
+        if signalled == 0 {
+            return Err(SupervisorError::NotRunning(instance_target(name, only)));
+        }
+        Ok(())
+    }
+
+    /// Clears every FATAL instance back to STOPPED, forgetting its failures
+    /// and restart history, so that it can be started again.
+    pub fn reset_program(&self, name: &str) -> Result<(), SupervisorError> {
+        self.reset_instances(name, None)
+    }
+
+    pub fn reset_program_instance(&self, name: &str, instance: u32) -> Result<(), SupervisorError> {
+        self.reset_instances(name, Some(instance))
+    }
+
+    fn reset_instances(&self, name: &str, only: Option<u32>) -> Result<(), SupervisorError> {
+        let program = self.program(name)?;
+        let mut processes = self.processes.lock().unwrap();
+        let instances = processes.get_mut(name).map(Vec::as_mut_slice).unwrap_or_default();
+        if let Some(instance) = only {
+            if instance >= (instances.len() as u32).max(program.numprocs) {
+                return Err(SupervisorError::InstanceNotFound { program: name.to_string(), instance });
+            }
+        }
+        let mut changed = 0;
+        for process_info in instances {
+            if only.is_some_and(|instance| instance != process_info.instance) || process_info.state != ProcessState::Fatal {
+                continue;
+            }
+            process_info.state = ProcessState::Stopped;
+            process_info.failures = 0;
+            process_info.spawn_failures = 0;
+            process_info.recent_restarts.clear();
+            self.logger.event(Level::Info, "supervisor", "reset", &[("program", &name), ("instance", &process_info.instance)])
+                .expect("Failed to log message");
+            changed += 1;
+        }
+        if changed == 0 {
+            return Err(SupervisorError::NotFatal(instance_target(name, only)));
+        }
+        Ok(())
+    }
+
+    /// Freezes every running instance with SIGSTOP to its process group.
+    pub fn pause_program(&self, name: &str) -> Result<(), SupervisorError> {
+        self.pause_instances(name, None, true)
+    }
+
+    pub fn pause_program_instance(&self, name: &str, instance: u32) -> Result<(), SupervisorError> {
+        self.pause_instances(name, Some(instance), true)
+    }
+
+    /// Thaws every paused instance with SIGCONT to its process group.
+    pub fn resume_program(&self, name: &str) -> Result<(), SupervisorError> {
+        self.pause_instances(name, None, false)
+    }
+
+    pub fn resume_program_instance(&self, name: &str, instance: u32) -> Result<(), SupervisorError> {
+        self.pause_instances(name, Some(instance), false)
+    }
+
+    fn pause_instances(&self, name: &str, only: Option<u32>, pause: bool) -> Result<(), SupervisorError> {
+        let program = self.program(name)?;
+        let mut processes = self.processes.lock().unwrap();
+        let instances = processes.get_mut(name).map(Vec::as_mut_slice).unwrap_or_default();
+        if let Some(instance) = only {
+            if instance >= (instances.len() as u32).max(program.numprocs) {
+                return Err(SupervisorError::InstanceNotFound { program: name.to_string(), instance });
+            }
+        }
+        let mut changed = 0;
+        for process_info in instances {
+            if only.is_some_and(|instance| instance != process_info.instance) {
+                continue;
+            }
+            let instance = process_info.instance;
+            let pid = process_info.pid().unwrap_or_default();
+            match (pause, process_info.state) {
+                (true, ProcessState::Starting | ProcessState::Running) => {
+                    if let Err(e) = process_info.signal_group(libc::SIGSTOP) {
+                        self.logger.event(Level::Error, "supervisor", "pause_failed", &[("program", &name), ("instance", &instance), ("error", &e)])
+                            .expect("Failed to log message");
+                        continue;
+                    }
+                    process_info.state = ProcessState::Paused;
+                    process_info.paused_at = Some(Instant::now());
+                    self.logger.event(Level::Info, "supervisor", "paused", &[("program", &name), ("instance", &instance), ("pid", &pid)])
+                        .expect("Failed to log message");
+                    self.emit(Event::Paused { program: name.to_string(), instance, pid });
+                }
+                (false, ProcessState::Paused) => {
+                    if let Err(e) = process_info.signal_group(libc::SIGCONT) {
+                        self.logger.event(Level::Error, "supervisor", "resume_failed", &[("program", &name), ("instance", &instance), ("error", &e)])
+                            .expect("Failed to log message");
+                        continue;
+                    }
+                    if let Some(paused_at) = process_info.paused_at.take() {
+                        if !process_info.successfully_started {
+                            process_info.start_time += paused_at.elapsed();
+                        }
+                    }
+                    process_info.state = if process_info.successfully_started { ProcessState::Running } else { ProcessState::Starting };
+                    self.logger.event(Level::Info, "supervisor", "resumed", &[("program", &name), ("instance", &instance), ("pid", &pid)])
+                        .expect("Failed to log message");
+                    self.emit(Event::Resumed { program: name.to_string(), instance, pid });
+                }
+                _ => continue,
+            }
+            changed += 1;
+        }
+        if changed == 0 {
+            let target = instance_target(name, only);
+            return Err(if pause { SupervisorError::NotRunning(target) } else { SupervisorError::NotPaused(target) });
+        }
+        Ok(())
+    }
+
+    /// Stops a single instance if it is running, then starts it again.
+    pub fn restart_program_instance(&self, name: &str, instance: u32) -> Result<(), SupervisorError> {
+        match self.stop_program_instance(name, instance) {
+            Ok(()) | Err(SupervisorError::NotRunning(_)) => self.start_program_instance(name, instance),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Runs `numprocs` instances of a program until reset, starting the
+    /// missing ones or gracefully stopping the surplus if it is running. The
+    /// override survives reloads.
+    pub fn scale(&self, name: &str, numprocs: u32) -> Result<(), SupervisorError> {
+        let configured = self.program(name)?;
+        self.overrides.lock().unwrap().insert(name.to_string(), numprocs);
+        self.logger.event(Level::Info, "supervisor", "scaled", &[("program", &name), ("numprocs", &numprocs), ("configured", &configured.numprocs)])
+            .expect("Failed to log message");
+        self.resize(name, numprocs)
+    }
+
+    /// Drops the override set with `scale`, going back to the configured
+    /// `numprocs`.
+    pub fn reset_scale(&self, name: &str) -> Result<(), SupervisorError> {
+        self.program(name)?;
+        self.overrides.lock().unwrap().remove(name);
+        let numprocs = self.program(name)?.numprocs;
+        self.logger.event(Level::Info, "supervisor", "scaled", &[("program", &name), ("numprocs", &numprocs), ("configured", &numprocs)])
+            .expect("Failed to log message");
+        self.resize(name, numprocs)
+    }
+
+    fn resize(&self, name: &str, numprocs: u32) -> Result<(), SupervisorError> {
+        let running = self.processes
+            .lock()
+            .unwrap()
+            .get(name)
+            .is_some_and(|instances| instances.iter().any(|process_info| process_info.state.is_alive()));
+        // The surplus stops all together, within a single stoptime.
+        self.stop_matching(name, &self.program(name)?, |instance| instance >= numprocs);
+        if let Some(instances) = self.processes.lock().unwrap().get_mut(name) {
+            instances.truncate(numprocs as usize);
+        }
+        if !running {
+            return Ok(());
+        }
+        let mut failed = 0;
+        for instance in 0..numprocs {
+            match self.start_program_instance(name, instance) {
+                Ok(()) | Err(SupervisorError::AlreadyRunning(_)) => {}
+                Err(_) => failed += 1,
+            }
+        }
+        if failed > 0 {
+            return Err(SupervisorError::StartFailed { program: name.to_string(), failed });
+        }
+        Ok(())
+    }
+
+    /// Kills every live instance of a program without waiting for `stoptime`.
+    pub fn kill_program(&self, name: &str) {
+        let mut pending = Vec::new();
+        {
+            let mut processes = self.processes.lock().unwrap();
+            for process_info in processes.get_mut(name).into_iter().flatten() {
+                if !process_info.state.is_alive() {
+                    continue;
+                }
+                process_info.state = ProcessState::Stopping;
+                if let Err(e) = process_info.signal_group(libc::SIGKILL) {
+                    self.logger.event(Level::Error, "supervisor", "kill_failed", &[("program", &name), ("instance", &process_info.instance), ("error", &e)])
+                        .expect("Failed to log message");
+                }
+                pending.push((process_info.instance, process_info.pid().unwrap_or_default()));
+            }
+        }
+        self.await_exit(name, pending, None);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Stops every program in reverse start order with its stopsignal
+    /// and stoptime, and waits until all instances are reaped. Nothing is
+    /// started or restarted from then on. Instances that a stop already in
+    /// progress does not end in time are SIGKILLed.
+    pub fn shutdown(&self) -> ShutdownSummary {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        self.logger.event(Level::Info, "supervisor", "shutting_down", &[]).expect("Failed to log message");
+        let events = self.subscribe();
+        let names = self.program_names();
+        for (name, result) in self.stop_programs(&names) {
+            match result {
+                Ok(()) | Err(SupervisorError::NotRunning(_)) => {}
+                Err(e) => self.logger.event(Level::Error, "supervisor", "stop_failed", &[("program", &name), ("error", &e)])
+                    .expect("Failed to log message"),
+            }
+        }
+        let stoptime = self.programs.lock().unwrap().values().map(|program| program.stoptime).max().unwrap_or_default();
+        let deadline = Instant::now() + Duration::from_secs(stoptime.into()) + Duration::from_secs(1);
+        while Instant::now() < deadline && self.processes.lock().unwrap().values().flatten().any(|process_info| process_info.state.is_alive()) {
+            thread::sleep(Duration::from_millis(100));
+        }
+        self.kill_all();
+        let mut summary = ShutdownSummary::default();
+        for event in events.try_iter() {
+            match event {
+                Event::Stopped { .. } => summary.stopped += 1,
+                Event::Killed { .. } => summary.killed += 1,
+                _ => {}
+            }
+        }
+        let level = if summary.is_clean() { Level::Info } else { Level::Warn };
+        self.logger.event(level, "supervisor", "shutdown", &[("stopped", &summary.stopped), ("killed", &summary.killed)])
+            .expect("Failed to log message");
+        summary
+    }
+
+    /// Kills every live instance without waiting for `stoptime`.
+    pub fn kill_all(&self) {
+        let names: Vec<String> = self.processes.lock().unwrap().keys().cloned().collect();
+        for name in names {
+            self.kill_program(&name);
+        }
+    }
+
+    /// Re-reads the config file this supervisor was created from. A config
+    /// that cannot be read is published as a `ReloadFailed` event.
+    pub fn reload_config(&self) -> Result<(), SupervisorError> {
+        let path = self.config_path.clone().unwrap_or_else(|| PathBuf::from("config.yml"));
+        let config = match Config::from_file(path) {
+            Ok(config) => config,
+            Err(e) => {
+                self.emit(Event::ReloadFailed { error: e.to_string() });
+                return Err(SupervisorError::Config(e));
+            }
+        };
+        self.reload(config);
+        Ok(())
+    }
+
+    /// Switches to `config`: programs that changed or disappeared are
+    /// stopped side by side, then autostart programs that are not running
+    /// are started by an autostart job.
+    pub fn reload(&self, config: Config) {
+        let new_programs = config.programs;
+        let outdated: Vec<String> = self.programs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(name, program)| new_programs.get(*name) != Some(program))
+            .map(|(name, _)| name.clone())
+            .collect();
+        thread::scope(|scope| {
+            for name in &outdated {
+                scope.spawn(move || self.stop_program(name));
+            }
+        });
+        self.processes.lock().unwrap().retain(|name, _| new_programs.contains_key(name));
+        let mut overrides = self.overrides.lock().unwrap();
+        overrides.retain(|name, _| new_programs.contains_key(name));
+        for (name, numprocs) in overrides.iter() {
+            self.logger.event(Level::Warn, "supervisor", "override", &[("program", name), ("numprocs", numprocs), ("configured", &new_programs[name].numprocs)])
+                .expect("Failed to log message");
+        }
+        drop(overrides);
+        *self.programs.lock().unwrap() = new_programs;
+        *self.groups.lock().unwrap() = config.groups;
+        self.logger.event(Level::Info, "supervisor", "reloaded", &[])
+            .expect("Failed to log message");
+        self.emit(Event::Reloaded);
+        self.autostart_programs();
+    }
+
+    /// Reaps exited instances, puts the ones whose `autorestart` policy asks
+    /// for a restart in BACKOFF, starts the ones whose backoff has passed
+    /// and marks instances that have lived past `starttime`.
+    /// The monitor thread calls this once a second.
+    pub fn poll(&self) {
+        let mut processes_to_restart = Vec::new();
+        let mut given_up = Vec::new();
+        {
+            let mut processes = self.processes.lock().unwrap();
+            let programs = self.programs.lock().unwrap();
+
+            for (program_name, children) in processes.iter_mut() {
+                let Some(program) = programs.get(program_name) else {
+                    continue;
+                };
+                for process_info in children.iter_mut() {
+                    if process_info.state == ProcessState::Backoff {
+                        if process_info.next_retry.is_none_or(|at| at <= Instant::now()) && !self.is_shutting_down() {
+                            processes_to_restart.push((program_name.clone(), process_info.instance, program.clone()));
+                        }
+                        continue;
+                    }
+                    if !matches!(process_info.state, ProcessState::Starting | ProcessState::Running | ProcessState::Paused) {
+                        continue;
+                    }
+                    let Some(Ok(Some(status))) = process_info.child.as_mut().map(Child::try_wait) else {
+                        self.check_running_time(program_name, process_info, program.starttime.into());
+                        continue;
+                    };
+                    let instance = process_info.instance;
+                    let pid = process_info.pid().unwrap_or_default();
+                    let exit_code = process_info.reap(status, ProcessState::Exited).code();
+                    let expected_exit = program.exitcodes.contains(&exit_code);
+                    let level = if expected_exit { Level::Info } else { Level::Warn };
+                    self.logger.event(level, "monitor", "exited", &[("program", program_name), ("instance", &instance), ("pid", &pid), ("status", &exit_code), ("expected", &expected_exit)])
+                        .expect("Failed to log message");
+                    self.emit(Event::Exited { program: program_name.clone(), instance, pid, status: exit_code, expected: expected_exit });
+                    if program.should_restart(exit_code) && !self.is_shutting_down() && self.schedule_restart(program_name, program, process_info) {
+                        given_up.push(program_name.clone());
+                    }
+                }
+            }
+        }
+
+        for program_name in given_up {
+            self.report_dependents(&program_name);
+        }
+
+        for (program_name, instance, program) in processes_to_restart {
+            let mut processes = self.processes.lock().unwrap();
+            let Some(process_info) = processes.get_mut(&program_name).and_then(|instances| instances.get_mut(instance as usize)) else {
+                continue;
+            };
+            if process_info.state != ProcessState::Backoff {
+                continue;
+            }
+            match self.start_instance(&program_name, &program, process_info) {
+                Ok(()) if process_info.state == ProcessState::Backoff => {}
+                Ok(()) => {
+                    self.logger.event(Level::Info, "monitor", "restarted", &[("program", &program_name), ("instance", &instance), ("pid", &process_info.pid().unwrap_or_default())])
+                        .expect("Failed to log message");
+                }
+                Err(e) => {
+                    self.logger.event(Level::Error, "monitor", "restart_failed", &[("program", &program_name), ("instance", &instance), ("error", &e)])
+                        .expect("Failed to log message");
+                }
+            }
+        }
+    }
+}
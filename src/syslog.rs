@@ -0,0 +1,208 @@
+use std::ffi::CStr;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Deserialize;
+use crate::logger::{rfc3339, Level};
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Facility {
+    Kern,
+    User,
+    Mail,
+    Daemon,
+    Auth,
+    Syslog,
+    Lpr,
+    News,
+    Uucp,
+    Cron,
+    Authpriv,
+    Ftp,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl Facility {
+    fn code(&self) -> u8 {
+        match self {
+            Facility::Kern => 0,
+            Facility::User => 1,
+            Facility::Mail => 2,
+            Facility::Daemon => 3,
+            Facility::Auth => 4,
+            Facility::Syslog => 5,
+            Facility::Lpr => 6,
+            Facility::News => 7,
+            Facility::Uucp => 8,
+            Facility::Cron => 9,
+            Facility::Authpriv => 10,
+            Facility::Ftp => 11,
+            Facility::Local0 => 16,
+            Facility::Local1 => 17,
+            Facility::Local2 => 18,
+            Facility::Local3 => 19,
+            Facility::Local4 => 20,
+            Facility::Local5 => 21,
+            Facility::Local6 => 22,
+            Facility::Local7 => 23,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Emerg = 0,
+    Alert = 1,
+    Crit = 2,
+    Err = 3,
+    Warning = 4,
+    Notice = 5,
+    Info = 6,
+    Debug = 7,
+}
+
+impl From<Level> for Severity {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Debug => Severity::Debug,
+            Level::Info => Severity::Info,
+            Level::Warn => Severity::Warning,
+            Level::Error => Severity::Err,
+            Level::Crit => Severity::Crit,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFormat {
+    Rfc3164,
+    Rfc5424,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct SyslogConfig {
+    #[serde(default = "default_socket")]
+    pub socket: String,
+    #[serde(default = "default_facility")]
+    pub facility: Facility,
+    #[serde(default = "default_ident")]
+    pub ident: String,
+    #[serde(default = "default_format")]
+    pub format: SyslogFormat,
+}
+
+fn default_socket() -> String {
+    "/dev/log".to_string()
+}
+
+fn default_facility() -> Facility {
+    Facility::Daemon
+}
+
+fn default_ident() -> String {
+    "taskmaster".to_string()
+}
+
+fn default_format() -> SyslogFormat {
+    SyslogFormat::Rfc3164
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        SyslogConfig {
+            socket: default_socket(),
+            facility: default_facility(),
+            ident: default_ident(),
+            format: default_format(),
+        }
+    }
+}
+
+/// A connection to the local syslog daemon over a Unix datagram socket.
+pub struct Syslog {
+    socket: Mutex<UnixDatagram>,
+    config: SyslogConfig,
+    hostname: String,
+}
+
+impl Syslog {
+    pub fn connect(config: &SyslogConfig) -> io::Result<Self> {
+        Ok(Syslog {
+            socket: Mutex::new(open_socket(&config.socket)?),
+            config: config.clone(),
+            hostname: hostname(),
+        })
+    }
+
+    pub fn ident(&self) -> &str {
+        &self.config.ident
+    }
+
+    /// Sends one message. `app_name` is the RFC 3164 tag or the RFC 5424
+    /// APP-NAME, and `msgid` is only carried by RFC 5424 framing.
+    pub fn send(&self, severity: Severity, app_name: &str, procid: u32, msgid: &str, message: &str) -> io::Result<()> {
+        let priority = self.config.facility.code() * 8 + severity as u8;
+        let now = SystemTime::now();
+        let datagram = match self.config.format {
+            SyslogFormat::Rfc3164 => format!("<{}>{} {}[{}]: {}", priority, bsd_timestamp(now), app_name, procid, message),
+            SyslogFormat::Rfc5424 => format!(
+                "<{}>1 {} {} {} {} {} - {}",
+                priority,
+                rfc3339(now),
+                self.hostname,
+                nil_if_empty(app_name),
+                procid,
+                nil_if_empty(msgid),
+                message
+            ),
+        };
+        let mut socket = self.socket.lock().unwrap();
+        if socket.send(datagram.as_bytes()).is_err() {
+            // The daemon may have been restarted and recreated its socket.
+            *socket = open_socket(&self.config.socket)?;
+            socket.send(datagram.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn open_socket(path: &str) -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(path)?;
+    Ok(socket)
+}
+
+fn nil_if_empty(value: &str) -> &str {
+    if value.is_empty() { "-" } else { value }
+}
+
+fn hostname() -> String {
+    let mut buffer = [0 as libc::c_char; 256];
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr(), buffer.len()) };
+    if result != 0 {
+        return "-".to_string();
+    }
+    let hostname = unsafe { CStr::from_ptr(buffer.as_ptr()) };
+    hostname.to_string_lossy().into_owned()
+}
+
+/// Formats `time` as the local `Mmm dd hh:mm:ss` timestamp of RFC 3164.
+fn bsd_timestamp(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::localtime_r(&secs, &mut tm);
+    }
+    format!("{} {:>2} {:02}:{:02}:{:02}", MONTHS[tm.tm_mon as usize], tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
@@ -0,0 +1,188 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use taskmaster::{Config, Event, Logger, LoggingConfig, Supervisor};
+
+/// Helper programs the supervisor is run against. Each one is a small
+/// shell script written into the test directory.
+const HELPERS: &[(&str, &str)] = &[
+    ("sleeper.sh", "exec sleep \"${1:-60}\"\n"),
+    ("exit_with.sh", "sleep \"${2:-0}\"\nexit \"$1\"\n"),
+    ("ignore_term.sh", "trap '' TERM\necho ready > \"$1\"\nwhile true; do sleep 0.1; done\n"),
+    ("crash.sh", "kill -SEGV $$\n"),
+    ("forker.sh", "sleep 60 &\necho $! > \"$1\"\nsleep 60 &\nwait\n"),
+    ("report.sh", "umask > umask.out\necho \"$GREETING\" > env.out\npwd > pwd.out\nexec sleep 60\n"),
+    ("chatter.sh", "printf 'caf\\351\\n'\nsleep 0.2\necho out\necho err >&2\nexec sleep 60\n"),
+];
+
+/// A scratch directory holding the helper scripts, the supervisor log and
+/// whatever the programs write. It is removed when dropped.
+pub struct TestDir {
+    pub path: PathBuf,
+}
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "taskmaster-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("Failed to create test directory");
+        for (script, content) in HELPERS {
+            fs::write(path.join(script), content).expect("Failed to write helper script");
+        }
+        TestDir { path }
+    }
+
+    /// The command line running one of the helper scripts.
+    pub fn helper(&self, script: &str, args: &str) -> String {
+        format!("sh {} {}", self.path.join(script).display(), args)
+    }
+
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A program entry with defaults suited to tests; `extra` is appended as
+/// is and may override any of them.
+pub fn program(dir: &TestDir, name: &str, cmd: &str, extra: &str) -> String {
+    let mut yaml = format!(
+        "{name}:\n  cmd: \"{cmd}\"\n  workingdir: {dir}\n",
+        name = name,
+        cmd = cmd,
+        dir = dir.path.display()
+    );
+    let defaults = [
+        ("numprocs", "1"),
+        ("umask", "\"022\""),
+        ("autostart", "false"),
+        ("autorestart", "never"),
+        ("exitcodes", "[0]"),
+        ("startretries", "1"),
+        ("starttime", "0"),
+        ("stopsignal", "15"),
+        ("stoptime", "1"),
+    ];
+    for (key, value) in defaults {
+        if !extra.lines().any(|line| line.trim_start().starts_with(&format!("{}:", key))) {
+            yaml.push_str(&format!("  {}: {}\n", key, value));
+        }
+    }
+    for line in extra.lines() {
+        yaml.push_str(&format!("  {}\n", line));
+    }
+    yaml
+}
+
+/// A supervisor driven the way the binary drives it, with its monitor
+/// polling in the background until the harness is dropped.
+pub struct Harness {
+    pub supervisor: Supervisor,
+    pub events: Receiver<Event>,
+    running: Arc<AtomicBool>,
+}
+
+impl Harness {
+    pub fn new(dir: &TestDir, programs: &[String]) -> Self {
+        let logging = LoggingConfig {
+            file: Some(dir.file("taskmaster.log").display().to_string()),
+            ..LoggingConfig::default()
+        };
+        Harness::with_logging(programs, logging)
+    }
+
+    pub fn with_logging(programs: &[String], logging: LoggingConfig) -> Self {
+        let yaml = if programs.is_empty() { "{}".to_string() } else { programs.concat() };
+        let config = Config::parse(&yaml).expect("Invalid test config");
+        let logger = Arc::new(Logger::with_config(&logging).expect("Failed to create logger"));
+        let supervisor = Supervisor::new(config, logger);
+        let events = supervisor.subscribe();
+        let running = Arc::new(AtomicBool::new(true));
+        let monitor = supervisor.clone();
+        let monitor_running = Arc::clone(&running);
+        thread::spawn(move || {
+            while monitor_running.load(Ordering::SeqCst) {
+                monitor.poll();
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+        Harness { supervisor, events, running }
+    }
+
+    /// Waits for the next event matching `predicate`, skipping the others.
+    pub fn expect_event<F: Fn(&Event) -> bool>(&self, timeout: Duration, predicate: F) -> Event {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.events.recv_timeout(remaining) {
+                Ok(event) if predicate(&event) => return event,
+                Ok(_) => continue,
+                Err(_) => panic!("Timed out waiting for event"),
+            }
+        }
+    }
+
+    /// Asserts that no event matching `predicate` arrives within `duration`.
+    pub fn expect_no_event<F: Fn(&Event) -> bool>(&self, duration: Duration, predicate: F) {
+        let deadline = Instant::now() + duration;
+        while let Ok(event) = self.events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            assert!(!predicate(&event), "Unexpected event: {:?}", event);
+        }
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        self.supervisor.kill_all();
+    }
+}
+
+/// Polls `condition` until it holds or `timeout` expires.
+pub fn wait_until<F: Fn() -> bool>(timeout: Duration, condition: F) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if condition() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    condition()
+}
+
+pub fn read_trimmed(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_default().trim().to_string()
+}
+
+/// The one-letter state of `pid` from /proc, if it exists.
+fn process_state(pid: u32) -> Option<char> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    stat.rsplit(')').next()?.trim_start().chars().next()
+}
+
+/// Whether `pid` is a live process, zombies excluded.
+pub fn is_alive(pid: u32) -> bool {
+    process_state(pid).is_some_and(|state| state != 'Z')
+}
+
+/// Whether `pid` is stopped by a signal.
+pub fn is_stopped(pid: u32) -> bool {
+    process_state(pid) == Some('T')
+}
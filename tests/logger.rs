@@ -0,0 +1,250 @@
+mod common;
+
+use std::fs;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::time::Duration;
+use common::TestDir;
+use taskmaster::logger::Format;
+use taskmaster::syslog::{Facility, SyslogConfig, SyslogFormat};
+use taskmaster::{Level, Logger, LoggingConfig};
+
+/// A logger writing to `taskmaster.log` in `dir`, rotating past `max_bytes`.
+fn file_logger(dir: &TestDir, max_bytes: u64, backups: u32) -> Logger {
+    let logging = LoggingConfig {
+        file: Some(dir.file("taskmaster.log").display().to_string()),
+        max_bytes,
+        backups,
+        ..LoggingConfig::default()
+    };
+    Logger::with_config(&logging).expect("Failed to create logger")
+}
+
+fn write_events(logger: &Logger, range: std::ops::Range<u32>) {
+    for index in range {
+        logger.event(Level::Info, "test", "line", &[("index", &index)]).unwrap();
+    }
+}
+
+/// The `index` field of every line of `path`, in order.
+fn indexes(path: &Path) -> Vec<u32> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| line.rsplit_once("index=").expect("Line without an index").1.parse().unwrap())
+        .collect()
+}
+
+/// A logger writing to `taskmaster.log` in `dir` from `level` up, in
+/// `format`.
+fn format_logger(dir: &TestDir, level: Level, format: Format) -> Logger {
+    let logging = LoggingConfig {
+        file: Some(dir.file("taskmaster.log").display().to_string()),
+        level,
+        format,
+        ..LoggingConfig::default()
+    };
+    Logger::with_config(&logging).expect("Failed to create logger")
+}
+
+#[test]
+fn records_below_the_level_are_left_out() {
+    let dir = TestDir::new("level");
+    let logger = format_logger(&dir, Level::Warn, Format::Text);
+    for level in [Level::Debug, Level::Info, Level::Warn, Level::Error, Level::Crit] {
+        logger.event(level, "test", "line", &[("level", &level.as_str())]).unwrap();
+    }
+
+    let log = fs::read_to_string(dir.file("taskmaster.log")).unwrap();
+    let levels: Vec<&str> = log.lines().map(|line| line.rsplit_once("level=").unwrap().1).collect();
+    assert_eq!(levels, ["WARN", "ERROR", "CRIT"]);
+    assert!(!logger.enabled(Level::Info) && logger.enabled(Level::Warn));
+}
+
+#[test]
+fn json_records_are_one_object_per_line() {
+    let dir = TestDir::new("json");
+    let logger = format_logger(&dir, Level::Info, Format::Json);
+    logger.event(Level::Error, "supervisor", "exited", &[("program", &"web server"), ("status", &3)]).unwrap();
+    logger.event(Level::Info, "supervisor", "running", &[]).unwrap();
+
+    let log = fs::read_to_string(dir.file("taskmaster.log")).unwrap();
+    let records: Vec<serde_json::Value> = log.lines().map(|line| serde_json::from_str(line).expect("Not a JSON line")).collect();
+    assert_eq!(records.len(), 2);
+    let record = &records[0];
+    assert!(record["ts"].as_str().unwrap().ends_with('Z'));
+    assert_eq!(record["level"], "ERROR");
+    assert_eq!(record["pid"], std::process::id());
+    assert_eq!(record["component"], "supervisor");
+    assert_eq!(record["event"], "exited");
+    assert_eq!(record["fields"], serde_json::json!({ "program": "web server", "status": "3" }));
+    assert_eq!(records[1]["fields"], serde_json::json!({}));
+}
+
+#[test]
+fn text_values_with_spaces_quotes_or_equals_are_quoted() {
+    let dir = TestDir::new("text");
+    let logger = format_logger(&dir, Level::Info, Format::Text);
+    logger.event(Level::Info, "shell", "command", &[("plain", &"start"), ("spaced", &"start web"), ("equals", &"a=b"), ("quoted", &"say \"hi\""), ("empty", &"")]).unwrap();
+
+    let log = fs::read_to_string(dir.file("taskmaster.log")).unwrap();
+    let prefix = format!(" INFO  taskmaster[{}] shell: command ", std::process::id());
+    let (timestamp, fields) = log.trim_end().split_once(&prefix).expect("Unexpected text layout");
+    assert!(timestamp.ends_with('Z'));
+    assert_eq!(fields, r#"plain=start spaced="start web" equals="a=b" quoted="say \"hi\"" empty="""#);
+}
+
+#[test]
+fn rotation_shifts_backups_without_losing_lines() {
+    let dir = TestDir::new("rotate");
+    let logger = file_logger(&dir, 300, 20);
+    write_events(&logger, 0..40);
+
+    let log = dir.file("taskmaster.log");
+    let backups: Vec<_> = (1..=20).map(|index| dir.file(&format!("taskmaster.log.{}", index))).take_while(|path| path.exists()).collect();
+    assert!(backups.len() >= 2, "Expected several backups, got {}", backups.len());
+    let mut lines = Vec::new();
+    for path in backups.iter().rev().chain([&log]) {
+        assert!(fs::metadata(path).unwrap().len() <= 300);
+        lines.extend(indexes(path));
+    }
+    assert_eq!(lines, (0..40).collect::<Vec<_>>());
+}
+
+#[test]
+fn rotation_keeps_only_the_configured_backups() {
+    let dir = TestDir::new("rotate-backups");
+    let logger = file_logger(&dir, 300, 2);
+    write_events(&logger, 0..40);
+
+    assert!(dir.file("taskmaster.log.1").exists());
+    assert!(dir.file("taskmaster.log.2").exists());
+    assert!(!dir.file("taskmaster.log.3").exists());
+    let mut lines = indexes(&dir.file("taskmaster.log.2"));
+    lines.extend(indexes(&dir.file("taskmaster.log.1")));
+    lines.extend(indexes(&dir.file("taskmaster.log")));
+    assert_eq!(lines.last(), Some(&39));
+    assert!(lines.windows(2).all(|pair| pair[1] == pair[0] + 1));
+}
+
+#[test]
+fn rotation_without_backups_truncates_the_file() {
+    let dir = TestDir::new("rotate-truncate");
+    let logger = file_logger(&dir, 300, 0);
+    write_events(&logger, 0..40);
+
+    assert!(!dir.file("taskmaster.log.1").exists());
+    let log = dir.file("taskmaster.log");
+    assert!(fs::metadata(&log).unwrap().len() <= 300);
+    let lines = indexes(&log);
+    assert_eq!(lines.last(), Some(&39));
+    assert!(lines.windows(2).all(|pair| pair[1] == pair[0] + 1));
+}
+
+#[test]
+fn a_failed_rotation_keeps_appending_and_is_retried() {
+    let dir = TestDir::new("rotate-fails");
+    fs::create_dir(dir.file("taskmaster.log.1")).unwrap();
+    let logger = file_logger(&dir, 300, 1);
+    write_events(&logger, 0..20);
+
+    let log = fs::read_to_string(dir.file("taskmaster.log")).unwrap();
+    assert_eq!(log.matches("rotate_failed").count(), 1);
+    let lines: Vec<u32> = log.lines().filter_map(|line| line.rsplit_once("index=")).map(|(_, index)| index.parse().unwrap()).collect();
+    assert_eq!(lines, (0..20).collect::<Vec<_>>());
+
+    fs::remove_dir(dir.file("taskmaster.log.1")).unwrap();
+    write_events(&logger, 20..21);
+    assert!(dir.file("taskmaster.log.1").is_file());
+    assert_eq!(indexes(&dir.file("taskmaster.log")), [20]);
+}
+
+#[test]
+fn reopen_creates_a_new_file_after_the_old_one_is_moved() {
+    let dir = TestDir::new("reopen");
+    let logger = file_logger(&dir, 0, 5);
+    write_events(&logger, 0..3);
+
+    fs::rename(dir.file("taskmaster.log"), dir.file("taskmaster.log.old")).unwrap();
+    write_events(&logger, 3..4);
+    assert!(!dir.file("taskmaster.log").exists());
+    logger.reopen().unwrap();
+    write_events(&logger, 4..6);
+
+    assert_eq!(indexes(&dir.file("taskmaster.log.old")), [0, 1, 2, 3]);
+    assert_eq!(indexes(&dir.file("taskmaster.log")), [4, 5]);
+}
+
+/// A syslog daemon stand-in bound in `dir`, and a logger sending to it.
+fn syslog_logger(dir: &TestDir, format: SyslogFormat) -> (UnixDatagram, Logger) {
+    let socket = dir.file("log.sock");
+    let daemon = UnixDatagram::bind(&socket).unwrap();
+    daemon.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let logging = LoggingConfig {
+        file: Some(dir.file("taskmaster.log").display().to_string()),
+        syslog: Some(SyslogConfig {
+            socket: socket.display().to_string(),
+            facility: Facility::Local3,
+            ident: "tm-test".to_string(),
+            format,
+        }),
+        ..LoggingConfig::default()
+    };
+    (daemon, Logger::with_config(&logging).expect("Failed to create logger"))
+}
+
+fn receive(daemon: &UnixDatagram) -> String {
+    let mut buffer = [0; 4096];
+    let len = daemon.recv(&mut buffer).expect("No datagram");
+    String::from_utf8_lossy(&buffer[..len]).into_owned()
+}
+
+#[test]
+fn syslog_rfc3164_framing_carries_the_priority_and_ident() {
+    let dir = TestDir::new("syslog-3164");
+    let (daemon, logger) = syslog_logger(&dir, SyslogFormat::Rfc3164);
+    logger.event(Level::Warn, "test", "line", &[("index", &1)]).unwrap();
+
+    // local3 is facility 19 and WARN maps to severity 4: 19 * 8 + 4.
+    let datagram = receive(&daemon);
+    let rest = datagram.strip_prefix("<156>").expect("Wrong PRI");
+    let (timestamp, message) = rest.split_at(15);
+    assert_eq!(timestamp.as_bytes()[3], b' ');
+    assert_eq!(&timestamp[6..7], " ");
+    assert_eq!(message, format!(" tm-test[{}]: test: line index=1", std::process::id()));
+}
+
+#[test]
+fn syslog_rfc5424_framing_carries_the_priority_ident_and_msgid() {
+    let dir = TestDir::new("syslog-5424");
+    let (daemon, logger) = syslog_logger(&dir, SyslogFormat::Rfc5424);
+    logger.event(Level::Crit, "test", "line", &[("index", &2)]).unwrap();
+
+    // CRIT maps to severity 2: 19 * 8 + 2.
+    let datagram = receive(&daemon);
+    let fields: Vec<&str> = datagram.splitn(8, ' ').collect();
+    assert_eq!(fields[0], "<154>1");
+    assert!(fields[1].ends_with('Z') && fields[1].contains('T'));
+    assert_eq!(fields[3], "tm-test");
+    assert_eq!(fields[4], std::process::id().to_string());
+    assert_eq!(fields[5], "line");
+    assert_eq!(fields[6], "-");
+    assert_eq!(fields[7], "test: line index=2");
+}
+
+#[test]
+fn a_vanished_syslog_keeps_the_file_and_is_reported_once() {
+    let dir = TestDir::new("syslog-gone");
+    let (daemon, logger) = syslog_logger(&dir, SyslogFormat::Rfc3164);
+    write_events(&logger, 0..1);
+    receive(&daemon);
+    drop(daemon);
+    fs::remove_file(dir.file("log.sock")).unwrap();
+
+    write_events(&logger, 1..4);
+    assert_eq!(logger.syslog_failures(), 3);
+    let log = fs::read_to_string(dir.file("taskmaster.log")).unwrap();
+    assert_eq!(log.matches("syslog_failed").count(), 1);
+    let lines: Vec<u32> = log.lines().filter_map(|line| line.rsplit_once("index=")).map(|(_, index)| index.parse().unwrap()).collect();
+    assert_eq!(lines, [0, 1, 2, 3]);
+}
@@ -0,0 +1,712 @@
+mod common;
+
+use std::fs;
+use std::os::unix::net::UnixDatagram;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use common::{is_alive, is_stopped, program, read_trimmed, wait_until, Harness, TestDir};
+use taskmaster::signals;
+use taskmaster::syslog::{Facility, SyslogConfig};
+use taskmaster::{Config, ConfigError, Event, ExitStatus, Logger, LoggingConfig, Notifications, ProcessState, Supervisor, SupervisorError};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[test]
+fn autorestart_always_restarts_after_an_expected_exit() {
+    let dir = TestDir::new("always");
+    let harness = Harness::new(&dir, &[program(&dir, "job", &dir.helper("exit_with.sh", "0"), "autorestart: always")]);
+
+    harness.supervisor.start_program("job").unwrap();
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Exited { status: 0, expected: true, .. }));
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Spawned { instance: 0, .. }));
+}
+
+#[test]
+fn autorestart_unexpected_ignores_listed_exitcodes() {
+    let dir = TestDir::new("expected");
+    let harness = Harness::new(&dir, &[program(&dir, "job", &dir.helper("exit_with.sh", "2"), "autorestart: unexpected\nexitcodes: [0, 2]")]);
+
+    harness.supervisor.start_program("job").unwrap();
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Exited { status: 2, expected: true, .. }));
+    harness.expect_no_event(Duration::from_millis(500), |e| matches!(e, Event::Spawned { .. }));
+}
+
+#[test]
+fn autorestart_unexpected_restarts_on_other_exitcodes() {
+    let dir = TestDir::new("unexpected");
+    let harness = Harness::new(&dir, &[program(&dir, "job", &dir.helper("exit_with.sh", "3"), "autorestart: unexpected")]);
+
+    harness.supervisor.start_program("job").unwrap();
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Exited { status: 3, expected: false, .. }));
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Spawned { .. }));
+}
+
+#[test]
+fn autorestart_never_leaves_a_crashed_program_down() {
+    let dir = TestDir::new("never");
+    let harness = Harness::new(&dir, &[program(&dir, "job", &dir.helper("crash.sh", ""), "autorestart: never")]);
+
+    harness.supervisor.start_program("job").unwrap();
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Exited { expected: false, .. }));
+    harness.expect_no_event(Duration::from_millis(500), |e| matches!(e, Event::Spawned { .. }));
+    let instance = &harness.supervisor.status()[0].instances[0];
+    assert_eq!(instance.state, ProcessState::Exited);
+    assert_eq!(instance.pid, None);
+    assert_eq!(instance.last_exit, Some(ExitStatus::Signal(libc::SIGSEGV)));
+}
+
+#[test]
+fn status_tracks_restarts_and_last_exit() {
+    let dir = TestDir::new("status");
+    let harness = Harness::new(&dir, &[program(&dir, "job", &dir.helper("exit_with.sh", "4 0.2"), "autorestart: always\nnumprocs: 2")]);
+
+    let status = harness.supervisor.status();
+    assert_eq!(status[0].instances.len(), 2);
+    assert!(status[0].instances.iter().all(|instance| instance.state == ProcessState::Stopped && instance.started_at.is_none()));
+
+    harness.supervisor.start_program("job").unwrap();
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Running { instance: 1, .. }));
+    assert!(wait_until(TIMEOUT, || harness.supervisor.status()[0].instances[1].restarts >= 1));
+    let instance = &harness.supervisor.status()[0].instances[1];
+    assert_eq!(instance.last_exit, Some(ExitStatus::Code(4)));
+    assert!(instance.started_at.is_some());
+}
+
+#[test]
+fn startretries_bounds_spawn_attempts_with_a_backoff_between_them() {
+    let dir = TestDir::new("retries");
+    let harness = Harness::new(&dir, &[program(&dir, "job", "/nonexistent/binary", "startretries: 3\nbackoff_initial: 0.2\nbackoff_jitter: 0")]);
+
+    let events = harness.supervisor.subscribe();
+    harness.supervisor.start_program("job").unwrap();
+    let instance = &harness.supervisor.status()[0].instances[0];
+    assert_eq!(instance.state, ProcessState::Backoff);
+    assert!(instance.next_retry.is_some());
+    let results = harness.supervisor.await_running(&events, &[("job".to_string(), None)], |_| {});
+    assert!(matches!(results.as_slice(), [(_, Err(SupervisorError::StartFailed { failed: 1, .. }))]));
+    for delay in [200, 400] {
+        harness.expect_event(TIMEOUT, |e| matches!(e, Event::SpawnFailed { .. }));
+        harness.expect_event(TIMEOUT, |e| matches!(e, Event::Backoff { delay_ms, .. } if *delay_ms == delay));
+    }
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::SpawnFailed { .. }));
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Fatal { instance: 0, .. }));
+}
+
+#[test]
+fn numprocs_starts_every_instance() {
+    let dir = TestDir::new("numprocs");
+    let harness = Harness::new(&dir, &[program(&dir, "job", &dir.helper("sleeper.sh", ""), "numprocs: 3")]);
+
+    harness.supervisor.start_program("job").unwrap();
+    let status = harness.supervisor.status();
+    let instances: Vec<u32> = status[0].instances.iter().map(|instance| instance.instance).collect();
+    assert_eq!(instances, vec![0, 1, 2]);
+    assert!(matches!(harness.supervisor.start_program("job"), Err(SupervisorError::AlreadyRunning(_))));
+}
+
+#[test]
+fn stop_uses_stopsignal_before_stoptime() {
+    let dir = TestDir::new("stop");
+    let harness = Harness::new(&dir, &[program(&dir, "job", &dir.helper("sleeper.sh", ""), "stoptime: 5")]);
+
+    harness.supervisor.start_program("job").unwrap();
+    let started = Instant::now();
+    harness.supervisor.stop_program("job").unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Stopped { .. }));
+    assert!(matches!(harness.supervisor.stop_program("job"), Err(SupervisorError::NotRunning(_))));
+}
+
+#[test]
+fn stop_escalates_to_sigkill_after_stoptime() {
+    let dir = TestDir::new("escalation");
+    let ready = dir.file("ready");
+    let harness = Harness::new(&dir, &[program(&dir, "job", &dir.helper("ignore_term.sh", &ready.display().to_string()), "stoptime: 1")]);
+
+    harness.supervisor.start_program("job").unwrap();
+    assert!(wait_until(TIMEOUT, || ready.exists()));
+    let started = Instant::now();
+    harness.supervisor.stop_program("job").unwrap();
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Killed { .. }));
+}
+
+#[test]
+fn stop_reaches_grandchildren() {
+    let dir = TestDir::new("grandchildren");
+    let pid_file = dir.file("grandchild.pid");
+    let harness = Harness::new(&dir, &[program(&dir, "job", &dir.helper("forker.sh", &pid_file.display().to_string()), "")]);
+
+    harness.supervisor.start_program("job").unwrap();
+    assert!(wait_until(TIMEOUT, || !read_trimmed(&pid_file).is_empty()));
+    let grandchild: u32 = read_trimmed(&pid_file).parse().unwrap();
+    assert!(is_alive(grandchild));
+    harness.supervisor.stop_program("job").unwrap();
+    assert!(wait_until(TIMEOUT, || !is_alive(grandchild)));
+}
+
+#[test]
+fn umask_env_and_workingdir_are_applied() {
+    let dir = TestDir::new("environment");
+    let harness = Harness::new(&dir, &[program(&dir, "job", &dir.helper("report.sh", ""), "umask: \"027\"\nenv:\n  GREETING: hello")]);
+
+    harness.supervisor.start_program("job").unwrap();
+    assert!(wait_until(TIMEOUT, || dir.file("pwd.out").exists()));
+    assert!(wait_until(TIMEOUT, || !read_trimmed(&dir.file("pwd.out")).is_empty()));
+    assert_eq!(read_trimmed(&dir.file("umask.out")), "0027");
+    assert_eq!(read_trimmed(&dir.file("env.out")), "hello");
+    assert_eq!(read_trimmed(&dir.file("pwd.out")), dir.path.display().to_string());
+}
+
+/// A harness whose syslog is a stand-in datagram socket in `dir`, on the
+/// local3 facility.
+fn syslog_harness(dir: &TestDir, programs: &[String]) -> (UnixDatagram, Harness) {
+    let socket = dir.file("log.sock");
+    let daemon = UnixDatagram::bind(&socket).unwrap();
+    daemon.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let logging = LoggingConfig {
+        file: Some(dir.file("taskmaster.log").display().to_string()),
+        syslog: Some(SyslogConfig { socket: socket.display().to_string(), facility: Facility::Local3, ..SyslogConfig::default() }),
+        ..LoggingConfig::default()
+    };
+    (daemon, Harness::with_logging(programs, logging))
+}
+
+/// The next datagram whose tag is `tag`, skipping the supervisor's own.
+fn receive_tagged(daemon: &UnixDatagram, tag: &str) -> String {
+    let mut buffer = [0; 4096];
+    loop {
+        let len = daemon.recv(&mut buffer).expect("No datagram");
+        let datagram = String::from_utf8_lossy(&buffer[..len]).into_owned();
+        if datagram.contains(&format!(" {}[", tag)) {
+            return datagram;
+        }
+    }
+}
+
+#[test]
+fn output_reaches_syslog_tagged_by_instance_and_severity() {
+    let dir = TestDir::new("output-syslog");
+    let extra = format!("stdout_syslog: true\nstderr_syslog: true\nstdout: {}", dir.file("out.log").display());
+    let (daemon, harness) = syslog_harness(&dir, &[program(&dir, "job", &dir.helper("chatter.sh", ""), &extra)]);
+
+    harness.supervisor.start_program("job").unwrap();
+    let pid = harness.supervisor.status()[0].instances[0].pid.unwrap();
+    // local3 is facility 19: info is 19 * 8 + 6 and err 19 * 8 + 3.
+    let first = receive_tagged(&daemon, "job.0");
+    assert!(first.starts_with("<158>"), "{}", first);
+    assert!(first.ends_with(&format!(" job.0[{}]: caf{}", pid, char::REPLACEMENT_CHARACTER)), "{}", first);
+    let mut rest = [receive_tagged(&daemon, "job.0"), receive_tagged(&daemon, "job.0")];
+    rest.sort();
+    assert!(rest[0].starts_with("<155>") && rest[0].ends_with("]: err"), "{}", rest[0]);
+    assert!(rest[1].starts_with("<158>") && rest[1].ends_with("]: out"), "{}", rest[1]);
+    assert!(wait_until(TIMEOUT, || fs::read(dir.file("out.log")).unwrap_or_default() == b"caf\xe9\nout\n"));
+    assert!(is_alive(pid));
+}
+
+#[test]
+fn an_unusable_output_fails_before_anything_is_spawned() {
+    let dir = TestDir::new("output-unusable");
+    let extra = "stdout_syslog: true\nstdout: /nonexistent/taskmaster/out.log";
+    let (_daemon, harness) = syslog_harness(&dir, &[program(&dir, "job", &dir.helper("report.sh", ""), extra)]);
+
+    assert!(harness.supervisor.start_program("job").is_err());
+    thread::sleep(Duration::from_millis(300));
+    assert!(!dir.file("pwd.out").exists());
+    let instance = &harness.supervisor.status()[0].instances[0];
+    assert_eq!(instance.state, ProcessState::Fatal);
+    assert_eq!(instance.pid, None);
+}
+
+#[test]
+fn unknown_programs_are_reported() {
+    let dir = TestDir::new("unknown");
+    let harness = Harness::new(&dir, &[]);
+
+    assert!(matches!(harness.supervisor.start_program("ghost"), Err(SupervisorError::NotFound(_))));
+    assert!(matches!(harness.supervisor.stop_program("ghost"), Err(SupervisorError::NotFound(_))));
+}
+
+#[test]
+fn status_json_follows_the_schema() {
+    let dir = TestDir::new("json");
+    let harness = Harness::new(&dir, &[program(&dir, "job", &dir.helper("sleeper.sh", ""), "numprocs: 2")]);
+
+    harness.supervisor.start_program("job").unwrap();
+    let json = harness.supervisor.status()[0].to_json();
+    assert_eq!(json["name"], "job");
+    assert_eq!(json["config"]["numprocs"], 2);
+    assert_eq!(json["config"]["autorestart"], "never");
+    let instance = &json["instances"][1];
+    assert_eq!(instance["name"], "job:1");
+    assert_eq!(instance["state"], "RUNNING");
+    assert!(instance["pid"].is_u64());
+    assert!(instance["started_at"].is_string());
+    assert!(instance["last_exit"].is_null());
+    assert_eq!(instance["restarts"], 0);
+}
+
+#[test]
+fn targets_expand_all_and_globs() {
+    let dir = TestDir::new("targets");
+    let sleeper = dir.helper("sleeper.sh", "");
+    let harness = Harness::new(&dir, &[
+        program(&dir, "web-1", &sleeper, ""),
+        program(&dir, "web-2", &sleeper, ""),
+        program(&dir, "worker", &sleeper, ""),
+    ]);
+
+    assert_eq!(harness.supervisor.resolve("all").unwrap(), vec!["web-1", "web-2", "worker"]);
+    assert_eq!(harness.supervisor.resolve("web-*").unwrap(), vec!["web-1", "web-2"]);
+    assert_eq!(harness.supervisor.resolve("w?rker").unwrap(), vec!["worker"]);
+    assert_eq!(harness.supervisor.resolve("worker").unwrap(), vec!["worker"]);
+    assert!(matches!(harness.supervisor.resolve("db-*"), Err(SupervisorError::NotFound(_))));
+    assert!(matches!(harness.supervisor.resolve("web"), Err(SupervisorError::NotFound(_))));
+}
+
+#[test]
+fn groups_start_in_priority_order_and_stop_in_reverse() {
+    let dir = TestDir::new("groups");
+    let sleeper = dir.helper("sleeper.sh", "");
+    let mut programs = vec![
+        program(&dir, "api", &sleeper, "priority: 20"),
+        program(&dir, "nginx", &sleeper, "priority: 10"),
+        program(&dir, "worker", &sleeper, ""),
+    ];
+    programs.push("groups:\n  web: [api, nginx]\n".to_string());
+    let harness = Harness::new(&dir, &programs);
+
+    assert_eq!(harness.supervisor.resolve("web:*").unwrap(), vec!["api", "nginx"]);
+    assert_eq!(harness.supervisor.resolve("web:api").unwrap(), vec!["api"]);
+    assert!(matches!(harness.supervisor.resolve("web:worker"), Err(SupervisorError::NotFound(_))));
+
+    let names = harness.supervisor.resolve("web:*").unwrap();
+    let started: Vec<String> = harness.supervisor.start_programs(&names).into_iter().map(|(name, _)| name).collect();
+    assert_eq!(started, vec!["nginx", "api"]);
+    let stopped: Vec<String> = harness.supervisor.stop_programs(&names).into_iter().map(|(name, _)| name).collect();
+    assert_eq!(stopped, vec!["api", "nginx"]);
+}
+
+#[test]
+fn groups_must_list_known_programs() {
+    let dir = TestDir::new("bad-group");
+    let yaml = format!("{}groups:\n  web: [api, ghost]\n", program(&dir, "api", &dir.helper("sleeper.sh", ""), ""));
+    assert!(matches!(Config::parse(&yaml), Err(ConfigError::Invalid(_))));
+}
+
+#[test]
+fn single_instances_are_controlled_without_renumbering() {
+    let dir = TestDir::new("instances");
+    let harness = Harness::new(&dir, &[program(&dir, "job", &dir.helper("sleeper.sh", ""), "numprocs: 3")]);
+
+    harness.supervisor.start_program("job").unwrap();
+    let pids: Vec<Option<u32>> = harness.supervisor.status()[0].instances.iter().map(|instance| instance.pid).collect();
+
+    harness.supervisor.stop_program_instance("job", 1).unwrap();
+    let states: Vec<ProcessState> = harness.supervisor.status()[0].instances.iter().map(|instance| instance.state).collect();
+    assert_eq!(states, vec![ProcessState::Running, ProcessState::Stopped, ProcessState::Running]);
+    assert!(matches!(harness.supervisor.stop_program_instance("job", 1), Err(SupervisorError::NotRunning(_))));
+
+    harness.supervisor.restart_program_instance("job", 2).unwrap();
+    harness.supervisor.start_program_instance("job", 1).unwrap();
+    let instances = &harness.supervisor.status()[0].instances;
+    assert_eq!(instances.iter().map(|instance| instance.instance).collect::<Vec<_>>(), vec![0, 1, 2]);
+    assert_eq!(instances[0].pid, pids[0]);
+    assert_ne!(instances[2].pid, pids[2]);
+    assert!(instances.iter().all(|instance| instance.state == ProcessState::Running));
+    assert!(matches!(
+        harness.supervisor.start_program_instance("job", 3),
+        Err(SupervisorError::InstanceNotFound { instance: 3, .. })
+    ));
+}
+
+#[test]
+fn scale_overrides_numprocs_until_reset() {
+    let dir = TestDir::new("scale");
+    let yaml = program(&dir, "job", &dir.helper("sleeper.sh", ""), "numprocs: 2");
+    let harness = Harness::new(&dir, std::slice::from_ref(&yaml));
+    let running = |supervisor: &Supervisor| {
+        supervisor.status()[0].instances.iter().filter(|instance| instance.state.is_alive()).count()
+    };
+
+    harness.supervisor.start_program("job").unwrap();
+    let first = harness.supervisor.status()[0].instances[0].pid;
+    harness.supervisor.scale("job", 4).unwrap();
+    assert_eq!(running(&harness.supervisor), 4);
+    assert_eq!(harness.supervisor.status()[0].instances[0].pid, first);
+
+    harness.supervisor.reload(Config::parse(&yaml).unwrap());
+    let status = &harness.supervisor.status()[0];
+    assert_eq!(status.numprocs_override, Some(4));
+    assert_eq!(running(&harness.supervisor), 4);
+
+    harness.supervisor.scale("job", 1).unwrap();
+    assert_eq!(harness.supervisor.status()[0].instances.len(), 1);
+    assert_eq!(harness.supervisor.status()[0].instances[0].pid, first);
+
+    harness.supervisor.reset_scale("job").unwrap();
+    assert_eq!(harness.supervisor.status()[0].numprocs_override, None);
+    assert_eq!(running(&harness.supervisor), 2);
+}
+
+#[test]
+fn scaling_down_stops_the_surplus_within_one_stoptime() {
+    let dir = TestDir::new("scale-down");
+    let ready = dir.file("ready");
+    let yaml = program(&dir, "job", &dir.helper("ignore_term.sh", &ready.display().to_string()), "numprocs: 4\nstoptime: 1");
+    let harness = Harness::new(&dir, &[yaml]);
+
+    harness.supervisor.start_program("job").unwrap();
+    assert!(wait_until(TIMEOUT, || ready.exists()));
+    thread::sleep(Duration::from_millis(200));
+    let started = Instant::now();
+    harness.supervisor.scale("job", 1).unwrap();
+    assert!(started.elapsed() < Duration::from_secs(2), "Took {:?}", started.elapsed());
+    let status = &harness.supervisor.status()[0];
+    assert_eq!(status.instances.len(), 1);
+    assert!(status.instances[0].state.is_alive());
+}
+
+#[test]
+fn signal_reaches_instances_and_the_monitor_sees_the_exit() {
+    let dir = TestDir::new("signal");
+    let harness = Harness::new(&dir, &[program(&dir, "job", &dir.helper("sleeper.sh", ""), "numprocs: 2")]);
+
+    assert!(matches!(harness.supervisor.signal_program("job", libc::SIGHUP), Err(SupervisorError::NotRunning(_))));
+    harness.supervisor.start_program("job").unwrap();
+    harness.supervisor.signal_program_instance("job", 1, libc::SIGHUP).unwrap();
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Exited { instance: 1, .. }));
+    let instances = &harness.supervisor.status()[0].instances;
+    assert_eq!(instances[0].state, ProcessState::Running);
+    assert_eq!(instances[1].state, ProcessState::Exited);
+    assert_eq!(instances[1].last_exit, Some(ExitStatus::Signal(libc::SIGHUP)));
+    assert_eq!(signals::parse("sigusr1"), Some(libc::SIGUSR1));
+    assert_eq!(signals::parse("HUP"), Some(libc::SIGHUP));
+    assert_eq!(signals::parse("BOGUS"), None);
+}
+
+#[test]
+fn paused_instances_are_neither_exited_nor_promoted() {
+    let dir = TestDir::new("pause");
+    let harness = Harness::new(&dir, &[program(&dir, "job", &dir.helper("sleeper.sh", ""), "starttime: 1\nstoptime: 5")]);
+
+    harness.supervisor.start_program("job").unwrap();
+    let pid = harness.supervisor.status()[0].instances[0].pid.unwrap();
+    harness.supervisor.pause_program("job").unwrap();
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Paused { .. }));
+    assert!(wait_until(TIMEOUT, || is_stopped(pid)));
+    harness.expect_no_event(Duration::from_millis(1500), |e| matches!(e, Event::Running { .. } | Event::Exited { .. }));
+    assert_eq!(harness.supervisor.status()[0].instances[0].state, ProcessState::Paused);
+    assert!(matches!(harness.supervisor.resume_program_instance("job", 0), Ok(())));
+    assert!(matches!(harness.supervisor.resume_program("job"), Err(SupervisorError::NotPaused(_))));
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Running { .. }));
+
+    harness.supervisor.pause_program("job").unwrap();
+    let started = Instant::now();
+    harness.supervisor.stop_program("job").unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Stopped { .. }));
+}
+
+#[test]
+fn a_slow_stop_does_not_block_supervision() {
+    let dir = TestDir::new("slow-stop");
+    let ready = dir.file("ready");
+    let harness = Harness::new(&dir, &[
+        program(&dir, "stubborn", &dir.helper("ignore_term.sh", &ready.display().to_string()), "stoptime: 3"),
+        program(&dir, "flappy", &dir.helper("exit_with.sh", "0 0.2"), "autorestart: always"),
+    ]);
+
+    harness.supervisor.start_program("stubborn").unwrap();
+    assert!(wait_until(TIMEOUT, || ready.exists()));
+    let supervisor = harness.supervisor.clone();
+    let stopping = thread::spawn(move || supervisor.stop_program("stubborn"));
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Stopping { .. }));
+
+    let started = Instant::now();
+    assert_eq!(harness.supervisor.status()[1].instances[0].state, ProcessState::Stopping);
+    harness.supervisor.start_program("flappy").unwrap();
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Exited { .. }));
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Spawned { .. }));
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert!(matches!(harness.supervisor.start_program("stubborn"), Err(SupervisorError::AlreadyRunning(_))));
+
+    stopping.join().unwrap().unwrap();
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Killed { .. }));
+}
+
+#[test]
+fn jobs_wait_for_starttime_and_report_early_exits() {
+    let dir = TestDir::new("jobs");
+    let harness = Harness::new(&dir, &[
+        program(&dir, "slow", &dir.helper("sleeper.sh", ""), "starttime: 1"),
+        program(&dir, "early", &dir.helper("exit_with.sh", "0 0.2"), "starttime: 2\nautorestart: always"),
+    ]);
+
+    let events = harness.supervisor.subscribe();
+    let started = Instant::now();
+    harness.supervisor.start_program("slow").unwrap();
+    assert!(started.elapsed() < Duration::from_millis(500));
+    assert_eq!(harness.supervisor.status()[1].instances[0].state, ProcessState::Starting);
+    let mut seen = Vec::new();
+    let results = harness.supervisor.await_running(&events, &[("slow".to_string(), None)], |event| seen.push(event.clone()));
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert!(matches!(results.as_slice(), [(name, Ok(()))] if name == "slow"));
+    assert!(seen.iter().any(|event| matches!(event, Event::Running { .. })));
+
+    let job = harness.supervisor.spawn_job("restart slow early", |supervisor| {
+        let events = supervisor.subscribe();
+        supervisor.restart_programs(&["slow".to_string()]);
+        supervisor.start_program("early").unwrap();
+        supervisor.await_running(&events, &[("slow".to_string(), None), ("early".to_string(), None)], |_| {})
+    });
+    assert!(!job.is_done());
+    assert_eq!(harness.supervisor.job(job.id()).unwrap().description(), "restart slow early");
+    let results = job.wait(Some(TIMEOUT)).expect("Job did not finish");
+    assert!(job.is_done() && !job.succeeded());
+    assert!(matches!(&results[0], (name, Ok(())) if name == "slow"));
+    assert!(matches!(&results[1], (name, Err(e)) if name == "early" && e.kind == "start_failed"));
+    assert_eq!(job.wait(None), Some(results));
+    assert_eq!(harness.supervisor.jobs().len(), 1);
+}
+
+#[test]
+fn only_the_latest_finished_jobs_are_kept() {
+    let dir = TestDir::new("jobs-bounded");
+    let harness = Harness::new(&dir, &[]);
+
+    let jobs: Vec<_> = (0..80).map(|_| harness.supervisor.spawn_job("noop", |_| Vec::new())).collect();
+    for job in &jobs {
+        assert_eq!(job.wait(Some(TIMEOUT)), Some(Vec::new()));
+    }
+    let last = harness.supervisor.spawn_job("last", |_| Vec::new());
+    let kept = harness.supervisor.jobs();
+    assert!(kept.len() < 80, "Kept {} jobs", kept.len());
+    assert!(harness.supervisor.job(jobs[0].id()).is_none());
+    assert!(harness.supervisor.job(last.id()).is_some());
+}
+
+#[test]
+fn a_failed_reload_is_published_and_keeps_the_programs() {
+    let dir = TestDir::new("reload-failed");
+    let yaml = format!("{}shell:\n  notifications: quiet\n", program(&dir, "job", &dir.helper("sleeper.sh", ""), ""));
+    fs::write(dir.file("config.yml"), &yaml).unwrap();
+    let config = Config::from_file(dir.file("config.yml")).unwrap();
+    assert_eq!(config.shell.notifications, Notifications::Quiet);
+    let logging = LoggingConfig { file: Some(dir.file("taskmaster.log").display().to_string()), ..LoggingConfig::default() };
+    let logger = Arc::new(Logger::with_config(&logging).unwrap());
+    let supervisor = Supervisor::new(config, logger);
+    let events = supervisor.subscribe();
+
+    fs::write(dir.file("config.yml"), "job: [").unwrap();
+    assert!(matches!(supervisor.reload_config(), Err(SupervisorError::Config(_))));
+    assert!(matches!(events.recv_timeout(TIMEOUT), Ok(Event::ReloadFailed { .. })));
+    assert_eq!(supervisor.program_names(), ["job"]);
+}
+
+#[test]
+fn shutdown_stops_in_reverse_priority_and_starts_nothing() {
+    let dir = TestDir::new("shutdown");
+    let ready = dir.file("ready");
+    let harness = Harness::new(&dir, &[
+        program(&dir, "db", &dir.helper("sleeper.sh", ""), "priority: 1"),
+        program(&dir, "stubborn", &dir.helper("ignore_term.sh", &ready.display().to_string()), "priority: 2"),
+        program(&dir, "flappy", &dir.helper("exit_with.sh", "0 0.3"), "autorestart: always\npriority: 3"),
+    ]);
+    for name in ["db", "stubborn", "flappy"] {
+        harness.supervisor.start_program(name).unwrap();
+    }
+    assert!(wait_until(TIMEOUT, || ready.exists()));
+
+    let summary = harness.supervisor.shutdown();
+    assert_eq!(summary.killed, 1);
+    assert!(!summary.is_clean());
+    assert!(harness.supervisor.status().iter().flat_map(|program| &program.instances).all(|instance| !instance.state.is_alive()));
+    let order: Vec<String> = harness.events.try_iter().filter_map(|event| match event {
+        Event::Stopping { program, .. } => Some(program),
+        _ => None,
+    }).collect();
+    let db = order.iter().position(|name| name == "db").unwrap();
+    assert!(order.iter().position(|name| name == "stubborn").unwrap() < db);
+    assert!(matches!(harness.supervisor.start_program("db"), Err(SupervisorError::ShuttingDown)));
+    harness.expect_no_event(Duration::from_millis(500), |e| matches!(e, Event::Spawned { .. }));
+}
+
+#[test]
+fn dependents_start_once_their_dependencies_are_running() {
+    let dir = TestDir::new("depends");
+    let sleeper = dir.helper("sleeper.sh", "");
+    let harness = Harness::new(&dir, &[
+        program(&dir, "api", &sleeper, "priority: 1\ndepends_on: [db]"),
+        program(&dir, "db", &sleeper, "priority: 900\nstarttime: 1"),
+        program(&dir, "broken", "/nonexistent/taskmaster-missing", ""),
+        program(&dir, "client", &sleeper, "depends_on: [broken]"),
+    ]);
+    let names = vec!["api".to_string(), "db".to_string()];
+    assert_eq!(harness.supervisor.start_order(&names), vec![vec!["db".to_string()], vec!["api".to_string()]]);
+    let repeated = vec!["api".to_string(), "db".to_string(), "api".to_string()];
+    assert_eq!(harness.supervisor.start_order(&repeated), vec![vec!["db".to_string()], vec!["api".to_string()]]);
+    assert!(matches!(
+        harness.supervisor.start_program("api"),
+        Err(SupervisorError::DependencyNotRunning { dependency, .. }) if dependency == "db"
+    ));
+
+    let results = harness.supervisor.start_programs(&names);
+    assert!(results.iter().all(|(_, result)| result.is_ok()));
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Running { program, .. } if program == "db"));
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Spawned { program, .. } if program == "api"));
+    let stopped: Vec<String> = harness.supervisor.stop_programs(&names).into_iter().map(|(name, _)| name).collect();
+    assert_eq!(stopped, vec!["api", "db"]);
+
+    let results = harness.supervisor.start_programs(&["client".to_string(), "broken".to_string()]);
+    assert!(matches!(&results[1], (name, Err(SupervisorError::DependencyFailed { dependency, .. })) if name == "client" && dependency == "broken"));
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::DependencyFatal { program, dependency } if program == "client" && dependency == "broken"));
+}
+
+#[test]
+fn a_dependency_already_up_does_not_hold_back_its_dependents() {
+    let dir = TestDir::new("depends-running");
+    let sleeper = dir.helper("sleeper.sh", "");
+    let harness = Harness::new(&dir, &[
+        program(&dir, "db", &sleeper, "starttime: 1"),
+        program(&dir, "api", &sleeper, "depends_on: [db]"),
+    ]);
+
+    harness.supervisor.start_program("db").unwrap();
+    let results = harness.supervisor.start_programs(&["db".to_string(), "api".to_string()]);
+    assert!(matches!(&results[0], (name, Err(SupervisorError::AlreadyRunning(_))) if name == "db"));
+    assert!(matches!(&results[1], (name, Ok(())) if name == "api"));
+    let results = harness.supervisor.start_programs(&["db".to_string(), "api".to_string()]);
+    assert!(matches!(&results[1], (name, Err(SupervisorError::AlreadyRunning(_))) if name == "api"));
+}
+
+#[test]
+fn dependencies_must_be_known_and_acyclic() {
+    let dir = TestDir::new("bad-depends");
+    let sleeper = dir.helper("sleeper.sh", "");
+    let unknown = program(&dir, "api", &sleeper, "depends_on: [ghost]");
+    assert!(matches!(Config::parse(&unknown), Err(ConfigError::Invalid(_))));
+    let cycle = [
+        program(&dir, "a", &sleeper, "depends_on: [b]"),
+        program(&dir, "b", &sleeper, "depends_on: [c]"),
+        program(&dir, "c", &sleeper, "depends_on: [a]"),
+    ].concat();
+    match Config::parse(&cycle) {
+        Err(ConfigError::Invalid(message)) => assert_eq!(message, "dependency cycle: a -> b -> c -> a"),
+        other => panic!("Expected a cycle, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn each_dependent_starts_as_soon_as_its_own_dependencies_are_running() {
+    let dir = TestDir::new("readiness");
+    let sleeper = dir.helper("sleeper.sh", "");
+    let harness = Harness::new(&dir, &[
+        program(&dir, "slow_db", &sleeper, "starttime: 2\npriority: 1"),
+        program(&dir, "cache", &sleeper, "starttime: 0\npriority: 2"),
+        program(&dir, "a", &sleeper, "depends_on: [slow_db]"),
+        program(&dir, "b", &sleeper, "depends_on: [cache]"),
+    ]);
+
+    let names: Vec<String> = ["a", "b", "cache", "slow_db"].iter().map(|name| name.to_string()).collect();
+    let starting = harness.supervisor.clone();
+    let start = thread::spawn(move || starting.start_programs(&names));
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Spawned { program, .. } if program == "b"));
+    let state = |name: &str| harness.supervisor.status().into_iter().find(|program| program.name == name).unwrap().instances[0].state;
+    assert_eq!(state("slow_db"), ProcessState::Starting);
+    assert_eq!(state("a"), ProcessState::Stopped);
+    let results = start.join().unwrap();
+    assert!(results.iter().all(|(_, result)| result.is_ok()));
+    assert_eq!(state("a"), ProcessState::Running);
+}
+
+#[test]
+fn autostart_starts_programs_side_by_side_and_logs_the_startup_time() {
+    let dir = TestDir::new("autostart");
+    let sleeper = dir.helper("sleeper.sh", "");
+    let harness = Harness::new(&dir, &[
+        program(&dir, "cache", &sleeper, "autostart: true\nstarttime: 1\npriority: 10"),
+        program(&dir, "db", &sleeper, "autostart: true\nstarttime: 1\npriority: 10\nnumprocs: 2"),
+        program(&dir, "api", &sleeper, "autostart: true\nstarttime: 1\npriority: 20"),
+        program(&dir, "manual", &sleeper, ""),
+    ]);
+
+    let started = Instant::now();
+    let job = harness.supervisor.autostart_programs().expect("Nothing to autostart");
+    assert_eq!(job.description(), "autostart");
+    let results = job.wait(Some(TIMEOUT)).expect("Autostart did not finish");
+    assert!(started.elapsed() < Duration::from_secs(3));
+    let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["cache", "db", "api"]);
+    assert!(job.succeeded());
+    let spawned: Vec<String> = harness.events.try_iter().filter_map(|event| match event {
+        Event::Spawned { program, .. } => Some(program),
+        _ => None,
+    }).collect();
+    assert_eq!(spawned.len(), 4);
+    assert_eq!(spawned.last().map(String::as_str), Some("api"));
+    assert!(harness.supervisor.status().iter().filter(|program| program.name != "manual").flat_map(|program| &program.instances).all(|instance| instance.state == ProcessState::Running));
+    assert!(fs::read_to_string(dir.file("taskmaster.log")).unwrap().contains("startup"));
+    assert!(harness.supervisor.autostart_programs().is_none());
+}
+
+#[test]
+fn backoff_grows_with_early_exits_and_resets_past_starttime() {
+    let dir = TestDir::new("backoff");
+    let backoff = "autorestart: always\nbackoff_initial: 0.2\nbackoff_max: 0.5\nbackoff_jitter: 0";
+    let harness = Harness::new(&dir, &[
+        program(&dir, "crashy", &dir.helper("exit_with.sh", "3"), &format!("starttime: 1\n{}", backoff)),
+        program(&dir, "steady", &dir.helper("exit_with.sh", "0 0.3"), &format!("starttime: 0\n{}", backoff)),
+    ]);
+    let delays = |name: &str, count: usize| -> Vec<u64> {
+        (0..count).map(|_| match harness.expect_event(TIMEOUT, |e| matches!(e, Event::Backoff { program, .. } if program == name)) {
+            Event::Backoff { delay_ms, .. } => delay_ms,
+            _ => unreachable!(),
+        }).collect()
+    };
+
+    harness.supervisor.start_program("crashy").unwrap();
+    assert_eq!(delays("crashy", 3), [200, 400, 500]);
+    harness.supervisor.stop_program("crashy").unwrap();
+    assert_eq!(harness.supervisor.status()[0].instances[0].state, ProcessState::Stopped);
+    harness.expect_no_event(Duration::from_millis(700), |e| matches!(e, Event::Spawned { program, .. } if program == "crashy"));
+
+    harness.supervisor.start_program("steady").unwrap();
+    assert_eq!(delays("steady", 2), [200, 200]);
+
+    for extra in ["backoff_multiplier: 0.5", "backoff_initial: 1e19\nbackoff_max: 1e30", "backoff_max: .inf"] {
+        let invalid = program(&dir, "job", &dir.helper("sleeper.sh", ""), extra);
+        assert!(matches!(Config::parse(&invalid), Err(ConfigError::Invalid(_))), "{}", extra);
+    }
+    let steep = program(&dir, "job", &dir.helper("sleeper.sh", ""), "backoff_initial: 86400\nbackoff_max: 86400\nbackoff_multiplier: 1e300\nbackoff_jitter: 1");
+    let steep = Config::parse(&steep).unwrap();
+    assert!(steep.programs["job"].backoff(u32::MAX) <= Duration::from_secs(86_400));
+}
+
+#[test]
+fn max_restarts_within_the_window_gives_up_until_reset() {
+    let dir = TestDir::new("max-restarts");
+    let harness = Harness::new(&dir, &[
+        program(&dir, "crashy", &dir.helper("exit_with.sh", "3"), "autorestart: always\nbackoff_initial: 0\nmax_restarts: 2\nrestart_window: 60"),
+        program(&dir, "api", &dir.helper("sleeper.sh", ""), "depends_on: [crashy]"),
+    ]);
+
+    harness.supervisor.start_program("crashy").unwrap();
+    for _ in 0..3 {
+        harness.expect_event(TIMEOUT, |e| matches!(e, Event::Spawned { .. }));
+        harness.expect_event(TIMEOUT, |e| matches!(e, Event::Exited { .. }));
+    }
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Fatal { instance: 0, .. }));
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::DependencyFatal { program, .. } if program == "api"));
+    harness.expect_no_event(Duration::from_millis(300), |e| matches!(e, Event::Spawned { .. }));
+    let instance = &harness.supervisor.status()[1].instances[0];
+    assert_eq!(instance.state, ProcessState::Fatal);
+    assert_eq!(instance.restarts, 2);
+
+    harness.supervisor.reset_program("crashy").unwrap();
+    assert_eq!(harness.supervisor.status()[1].instances[0].state, ProcessState::Stopped);
+    assert!(matches!(harness.supervisor.reset_program("crashy"), Err(SupervisorError::NotFatal(_))));
+    harness.supervisor.start_program("crashy").unwrap();
+    harness.expect_event(TIMEOUT, |e| matches!(e, Event::Spawned { .. }));
+}